@@ -1,11 +1,17 @@
+use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 
-use eyre::{Error, WrapErr};
+use eyre::{eyre, Error, WrapErr};
 use log::info;
 use needletail::{parse_fastx_file, parse_fastx_stdin, Sequence};
+use rayon::prelude::*;
 use sourmash::cmd::ComputeParameters;
+use sourmash::encodings::HashFunctions;
 use sourmash::index::storage::ToWriter;
-use sourmash::signature::Signature;
+use sourmash::signature::{Signature, SigsTrait};
+use sourmash::sketch::minhash::KmerMinHash;
+use sourmash::sketch::Sketch;
 
 fn open_parser<P: AsRef<Path>>(
     filename: P,
@@ -26,7 +32,7 @@ pub fn compute<P: AsRef<Path>>(
         let mut n = 0;
         let mut total_seq = 0;
 
-        let mut sig = Signature::from_params(&params);
+        let mut sig = Signature::from_params(params);
         sig.set_filename(filenames.iter().last().unwrap().as_ref().to_str().unwrap());
         sig.set_name(&params.merge().clone().unwrap());
 
@@ -37,7 +43,7 @@ pub fn compute<P: AsRef<Path>>(
                 filename.as_ref().to_str().unwrap()
             );
 
-            let mut parser = open_parser(&filename)?;
+            let mut parser = open_parser(filename)?;
 
             while let Some(record) = parser.next() {
                 let record = record?;
@@ -83,13 +89,13 @@ pub fn compute<P: AsRef<Path>>(
             .unwrap_or(format!("{}.sig", filename.as_ref().to_str().unwrap()));
 
         if params.singleton() {
-            let mut parser = open_parser(&filename)?;
+            let mut parser = open_parser(filename)?;
 
             while let Some(record) = parser.next() {
                 let record = record?;
-                let mut sig = Signature::from_params(&params);
+                let mut sig = Signature::from_params(params);
                 sig.set_name(&String::from_utf8(record.id().to_vec())?);
-                sig.set_filename(&filename.as_ref().to_str().unwrap());
+                sig.set_filename(filename.as_ref().to_str().unwrap());
 
                 let seq = record.normalize(false);
                 if params.input_is_protein() {
@@ -105,12 +111,12 @@ pub fn compute<P: AsRef<Path>>(
             let fname: String = filename.as_ref().to_str().unwrap().into();
             info!("... reading sequences from {}", &fname);
 
-            let mut sig = Signature::from_params(&params);
+            let mut sig = Signature::from_params(params);
             sig.set_filename(&fname);
 
             let mut name = None;
 
-            let mut parser = parse_fastx_file(&filename)?;
+            let mut parser = parse_fastx_file(filename)?;
             while let Some(record) = parser.next() {
                 let record = record?;
                 let seq = record.normalize(false);
@@ -148,7 +154,7 @@ pub fn compute<P: AsRef<Path>>(
 
         if let Some(ref output_name) = params.output() {
             let mut output = niffler::to_path(
-                &output_name,
+                output_name,
                 niffler::compression::Format::No,
                 niffler::compression::Level::One,
             )
@@ -161,11 +167,174 @@ pub fn compute<P: AsRef<Path>>(
     Ok(siglist)
 }
 
+pub fn minhash_from_sig(sig: &Signature) -> Result<KmerMinHash, Error> {
+    // Signatures are expected to be already selected down to one sketch,
+    // like the ones returned by `Signature::load_signatures`
+    match sig.sketches().into_iter().next() {
+        Some(Sketch::MinHash(mh)) => Ok(mh),
+        Some(Sketch::LargeMinHash(mh)) => Ok(mh.into()),
+        Some(Sketch::HyperLogLog(_)) => {
+            Err(eyre!("Signature {} is not a MinHash sketch", sig.name()))
+        }
+        None => Err(eyre!("Signature {} has no sketches", sig.name())),
+    }
+}
+
 pub fn compare(
     signatures: &[Signature],
     params: &CompareParameters,
 ) -> Result<Vec<Vec<f64>>, Error> {
-    unimplemented!();
+    let mhs = signatures
+        .iter()
+        .map(minhash_from_sig)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if params.containment || params.max_containment {
+        if let Some((sig, _)) = signatures.iter().zip(&mhs).find(|(_, mh)| mh.scaled() == 0) {
+            return Err(eyre!(
+                "must use scaled signatures with --containment and --max-containment, {} uses num",
+                sig.name()
+            ));
+        }
+    }
+
+    for (sig, mh) in signatures.iter().zip(&mhs).skip(1) {
+        mhs[0].check_compatible(mh).wrap_err_with(|| {
+            format!(
+                "Signature {} is not compatible with {}",
+                sig.name(),
+                signatures[0].name()
+            )
+        })?;
+    }
+
+    info!("comparing {} signatures", signatures.len());
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(params.processes)
+        .build()?;
+
+    let matrix = pool.install(|| {
+        mhs.par_iter()
+            .map(|mh| {
+                mhs.iter()
+                    .map(|other| {
+                        if params.containment {
+                            let c = containment(mh, other)?;
+                            if params.estimate_ani {
+                                Ok(ani_from_containment(c, mh.ksize()))
+                            } else {
+                                Ok(c)
+                            }
+                        } else if params.max_containment {
+                            let c = max_containment(mh, other)?;
+                            if params.estimate_ani {
+                                Ok(ani_from_containment(c, mh.ksize()))
+                            } else {
+                                Ok(c)
+                            }
+                        } else if params.estimate_ani {
+                            // ANI is only defined for Jaccard, ignore abundances
+                            Ok(ani_from_jaccard(mh.jaccard(other)?, mh.ksize()))
+                        } else {
+                            Ok(mh.similarity(other, params.ignore_abundance, false)?)
+                        }
+                    })
+                    .collect::<Result<Vec<f64>, Error>>()
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+
+    let labels: Vec<String> = signatures.iter().map(|sig| sig.name()).collect();
+
+    if let Some(ref output_name) = params.output {
+        let mut output = File::create(output_name)
+            .wrap_err_with(|| format!("Error creating output file {}", output_name))?;
+        write_npy(&matrix, &mut output)
+            .wrap_err_with(|| format!("Error saving to {}", output_name))?;
+
+        let labels_name = format!("{}.labels.txt", output_name);
+        let mut output = File::create(&labels_name)
+            .wrap_err_with(|| format!("Error creating output file {}", labels_name))?;
+        for label in &labels {
+            writeln!(output, "{}", label)?;
+        }
+        info!("saved similarity matrix to {}", output_name);
+    }
+
+    if let Some(ref csv_name) = params.csv {
+        let mut wrt = csv::Writer::from_path(csv_name)
+            .wrap_err_with(|| format!("Error creating output file {}", csv_name))?;
+        wrt.write_record(&labels)?;
+        for row in &matrix {
+            wrt.serialize(row)?;
+        }
+        wrt.flush()?;
+        info!("saved similarity matrix in CSV format to {}", csv_name);
+    }
+
+    Ok(matrix)
+}
+
+/// Fraction of the hashes in `mh` that are also present in `other`.
+pub fn containment(mh: &KmerMinHash, other: &KmerMinHash) -> Result<f64, Error> {
+    if mh.size() == 0 {
+        return Ok(0.0);
+    }
+    let common = mh.count_common(other, false)?;
+    Ok(common as f64 / mh.size() as f64)
+}
+
+/// Containment of the smaller sketch in the larger one.
+pub fn max_containment(mh: &KmerMinHash, other: &KmerMinHash) -> Result<f64, Error> {
+    let min_size = usize::min(mh.size(), other.size());
+    if min_size == 0 {
+        return Ok(0.0);
+    }
+    let common = mh.count_common(other, false)?;
+    Ok(common as f64 / min_size as f64)
+}
+
+/// Average nucleotide identity estimated from the Jaccard similarity
+/// of two sketches, using the Mash distance.
+pub fn ani_from_jaccard(jaccard: f64, ksize: usize) -> f64 {
+    if jaccard <= 0.0 {
+        return 0.0;
+    }
+    (2.0 * jaccard / (1.0 + jaccard)).powf(1.0 / ksize as f64)
+}
+
+/// Average nucleotide identity estimated from the containment
+/// of one sketch in another.
+pub fn ani_from_containment(containment: f64, ksize: usize) -> f64 {
+    if containment <= 0.0 {
+        return 0.0;
+    }
+    containment.powf(1.0 / ksize as f64)
+}
+
+/// Write a square matrix in the numpy `.npy` format,
+/// the same format used by `sourmash compare --output`.
+fn write_npy<W: Write>(matrix: &[Vec<f64>], writer: &mut W) -> Result<(), Error> {
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}), }}",
+        matrix.len(),
+        matrix.len()
+    );
+    // magic (6) + version (2) + header len (2) + header + '\n' must be a multiple of 64
+    let padding = 64 - (10 + header.len() + 1) % 64;
+    header.push_str(&" ".repeat(padding % 64));
+    header.push('\n');
+
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for row in matrix {
+        for value in row {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    Ok(())
 }
 
 pub struct CompareParameters {
@@ -180,12 +349,30 @@ pub struct CompareParameters {
     pub output: Option<String>,
     pub csv: Option<String>,
 
+    pub containment: bool,
+    pub max_containment: bool,
+    pub estimate_ani: bool,
+
     pub ignore_abundance: bool,
     pub traverse_directory: bool,
 
     pub processes: usize,
 }
 
+impl CompareParameters {
+    pub fn moltype(&self) -> HashFunctions {
+        if self.protein {
+            HashFunctions::murmur64_protein
+        } else if self.dayhoff {
+            HashFunctions::murmur64_dayhoff
+        } else if self.hp {
+            HashFunctions::murmur64_hp
+        } else {
+            HashFunctions::murmur64_DNA
+        }
+    }
+}
+
 impl Default for CompareParameters {
     fn default() -> Self {
         CompareParameters {
@@ -197,6 +384,9 @@ impl Default for CompareParameters {
             protein: false,
             output: None,
             csv: None,
+            containment: false,
+            max_containment: false,
+            estimate_ani: false,
             ignore_abundance: false,
             traverse_directory: false,
             processes: 1,
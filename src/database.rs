@@ -0,0 +1,525 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use eyre::Result;
+use log::info;
+
+use sourmash::encodings::HashFunctions;
+use sourmash::index::linear::LinearIndex;
+use sourmash::index::{Comparable, Index};
+use sourmash::signature::{Signature, SigsTrait};
+use sourmash::sketch::minhash::KmerMinHash;
+use sourmash::sketch::Sketch;
+
+use crate::cmd::minhash_from_sig;
+use crate::sbt::{Sbt, SbtNode};
+
+/// Search function taken by `SearchIndex::find`. Unlike the one used by `Index::find`,
+/// it knows whether it is checking an internal node or a leaf.
+pub type SearchFn<'a> = dyn Fn(SbtNode, &Signature, f64) -> bool + 'a;
+
+/// The parts of `Index` a `Database` needs, in a form that can be
+/// used as a trait object. Implemented by every index backend.
+pub trait SearchIndex {
+    fn find(
+        &self,
+        search_fn: &SearchFn,
+        sig: &Signature,
+        threshold: f64,
+    ) -> std::result::Result<Vec<&Signature>, sourmash::Error>;
+
+    fn insert(&mut self, sig: Signature) -> std::result::Result<(), sourmash::Error>;
+
+    fn save(&self, path: &Path) -> std::result::Result<(), sourmash::Error>;
+
+    fn signatures(&self) -> Vec<Signature>;
+
+    fn signature_refs(&self) -> Vec<&Signature>;
+
+    /// First signature in the index, without loading all of them.
+    fn first_signature(&self) -> Result<Option<Signature>>;
+
+    /// Whether `find` checks every signature anyway,
+    /// so they can be scored directly (and in parallel) instead.
+    fn is_linear(&self) -> bool {
+        false
+    }
+}
+
+impl SearchIndex for Sbt {
+    fn find(
+        &self,
+        search_fn: &SearchFn,
+        sig: &Signature,
+        threshold: f64,
+    ) -> std::result::Result<Vec<&Signature>, sourmash::Error> {
+        Sbt::find(self, search_fn, sig, threshold)
+    }
+
+    fn insert(&mut self, _sig: Signature) -> std::result::Result<(), sourmash::Error> {
+        Err(sourmash::Error::Internal {
+            message: "inserting into a loaded SBT is not supported".into(),
+        })
+    }
+
+    fn save(&self, _path: &Path) -> std::result::Result<(), sourmash::Error> {
+        Err(sourmash::Error::Internal {
+            message: "saving a loaded SBT is not supported".into(),
+        })
+    }
+
+    fn signatures(&self) -> Vec<Signature> {
+        self.signature_refs().into_iter().cloned().collect()
+    }
+
+    fn signature_refs(&self) -> Vec<&Signature> {
+        Sbt::signature_refs(self)
+    }
+
+    fn first_signature(&self) -> Result<Option<Signature>> {
+        Ok(Sbt::first_signature(self)?.cloned())
+    }
+}
+
+impl SearchIndex for LinearIndex<Signature> {
+    fn find(
+        &self,
+        search_fn: &SearchFn,
+        sig: &Signature,
+        threshold: f64,
+    ) -> std::result::Result<Vec<&Signature>, sourmash::Error> {
+        // every signature is a leaf
+        Ok(Index::signature_refs(self)
+            .into_iter()
+            .filter(|leaf| search_fn(SbtNode::Leaf(leaf), sig, threshold))
+            .collect())
+    }
+
+    fn insert(&mut self, sig: Signature) -> std::result::Result<(), sourmash::Error> {
+        Index::insert(self, sig)
+    }
+
+    fn save(&self, path: &Path) -> std::result::Result<(), sourmash::Error> {
+        Index::save(self, path)
+    }
+
+    fn signatures(&self) -> Vec<Signature> {
+        Index::signatures(self)
+    }
+
+    fn signature_refs(&self) -> Vec<&Signature> {
+        Index::signature_refs(self)
+    }
+
+    fn first_signature(&self) -> Result<Option<Signature>> {
+        Ok(Index::signature_refs(self)
+            .first()
+            .map(|sig| (*sig).clone()))
+    }
+
+    fn is_linear(&self) -> bool {
+        true
+    }
+}
+
+/// Loads `path` if it is in the format handled by a backend, returning
+/// `Ok(None)` if it isn't, and the number of signatures read from a
+/// signature file (zero for indices).
+type Loader = fn(&Path, Option<usize>, Option<HashFunctions>) -> Result<Option<(Database, usize)>>;
+
+/// Index backends, tried in order when loading a database.
+/// Supporting a new format only needs a `SearchIndex` implementation
+/// and a loader here.
+const BACKENDS: &[(&str, Loader)] = &[
+    ("SBT", load_sbt),
+    ("LinearIndex", load_linear_index),
+    ("signatures", load_signature_index),
+];
+
+pub struct Database {
+    index: Box<dyn SearchIndex>,
+    pub path: String,
+    pub locations: HashMap<String, Location>,
+    /// ksize and molecule type shared by all signatures, when known without loading them.
+    pub params: Option<(usize, HashFunctions)>,
+}
+
+/// The signatures of a linear database. Unlike the database itself they can be
+/// sent to another thread, and turned back into it with `Database::from_signatures`.
+pub struct DatabaseSignatures {
+    path: String,
+    sigs: Vec<Signature>,
+    locations: HashMap<String, Location>,
+    params: Option<(usize, HashFunctions)>,
+}
+
+/// Where a signature is stored in a database.
+#[derive(Clone)]
+pub struct Location {
+    pub position: usize,
+    pub path: String,
+}
+
+impl Index<'_> for Database {
+    type Item = Signature;
+
+    fn find<F>(
+        &self,
+        search_fn: F,
+        sig: &Self::Item,
+        threshold: f64,
+    ) -> std::result::Result<Vec<&Self::Item>, sourmash::Error>
+    where
+        F: Fn(&dyn Comparable<Self::Item>, &Self::Item, f64) -> bool,
+    {
+        self.find_nodes(
+            |node, sig, threshold| search_fn(node.comparable(), sig, threshold),
+            sig,
+            threshold,
+        )
+    }
+
+    fn insert(&mut self, node: Self::Item) -> std::result::Result<(), sourmash::Error> {
+        self.index.insert(node)
+    }
+
+    fn save<P: AsRef<Path>>(&self, path: P) -> std::result::Result<(), sourmash::Error> {
+        self.index.save(path.as_ref())
+    }
+
+    /// Check that `path` can be loaded by one of the backends.
+    fn load<P: AsRef<Path>>(path: P) -> std::result::Result<(), sourmash::Error> {
+        let path = path.as_ref();
+        match Database::from_path(path, None, None) {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err(sourmash::Error::Internal {
+                message: format!("no signatures found in {}", path.display()),
+            }),
+            Err(e) => Err(sourmash::Error::Internal {
+                message: format!("couldn't load {}: {}", path.display(), e),
+            }),
+        }
+    }
+
+    fn signatures(&self) -> Vec<Self::Item> {
+        self.index.signatures()
+    }
+
+    fn signature_refs(&self) -> Vec<&Self::Item> {
+        self.index.signature_refs()
+    }
+}
+
+impl Database {
+    /// Load `path` with the first backend that recognizes it, keeping only
+    /// signatures matching `ksize` and `moltype` from signature files.
+    /// Returns the number of signatures loaded (zero for indices).
+    pub fn from_path(
+        path: &Path,
+        ksize: Option<usize>,
+        moltype: Option<HashFunctions>,
+    ) -> Result<Option<(Database, usize)>> {
+        for (name, loader) in BACKENDS {
+            if let Some((db, n_sigs)) = loader(path, ksize, moltype)? {
+                if n_sigs > 0 {
+                    info!("loaded {} signatures from {}", n_sigs, db.path);
+                } else {
+                    info!("loaded {} {}", name, db.path);
+                }
+                return Ok(Some((db, n_sigs)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Like `Index::find`, with `search_fn` told whether each node is a leaf.
+    pub fn find_nodes<F>(
+        &self,
+        search_fn: F,
+        sig: &Signature,
+        threshold: f64,
+    ) -> std::result::Result<Vec<&Signature>, sourmash::Error>
+    where
+        F: Fn(SbtNode, &Signature, f64) -> bool,
+    {
+        self.index.find(&search_fn, sig, threshold)
+    }
+
+    /// MinHash from the first signature in the database,
+    /// with the parameters (ksize, molecule, scaled) shared by all of them.
+    pub fn template(&self) -> Result<Option<KmerMinHash>> {
+        self.index
+            .first_signature()?
+            .as_ref()
+            .map(minhash_from_sig)
+            .transpose()
+    }
+
+    pub fn first_signature(&self) -> Result<Option<Signature>> {
+        self.index.first_signature()
+    }
+
+    pub fn is_linear(&self) -> bool {
+        self.index.is_linear()
+    }
+
+    /// The signatures of a linear database, `None` for other indices.
+    pub fn into_signatures(self) -> Option<DatabaseSignatures> {
+        if !self.is_linear() {
+            return None;
+        }
+
+        Some(DatabaseSignatures {
+            sigs: self.index.signatures(),
+            path: self.path,
+            locations: self.locations,
+            params: self.params,
+        })
+    }
+
+    /// Rebuild a database from the signatures returned by `into_signatures`.
+    pub fn from_signatures(sigs: DatabaseSignatures) -> Result<Database> {
+        let mut index = LinearIndex::<Signature>::builder().build();
+        index.batch_insert(sigs.sigs)?;
+
+        Ok(Database {
+            index: Box::new(index),
+            path: sigs.path,
+            locations: sigs.locations,
+            params: sigs.params,
+        })
+    }
+
+    /// Keep only the signatures matching `ksize` and `moltype`, for databases
+    /// loaded without selecting them. Returns `None` if none of them match.
+    /// SBTs are returned unchanged, all their leaves share the same parameters.
+    pub fn select(self, ksize: usize, moltype: HashFunctions) -> Result<Option<Database>> {
+        if !self.is_linear() || self.params == Some((ksize, moltype)) {
+            return Ok(Some(self));
+        }
+
+        let sigs = self.index.signatures();
+        let n_sigs = sigs.len();
+        let sigs = select_signatures(sigs, Some(ksize), Some(moltype));
+        if sigs.is_empty() {
+            return Ok(None);
+        }
+
+        let index: Box<dyn SearchIndex> = if sigs.len() == n_sigs {
+            self.index
+        } else {
+            let mut index = LinearIndex::<Signature>::builder().build();
+            index.batch_insert(sigs)?;
+            Box::new(index)
+        };
+
+        Ok(Some(Database {
+            index,
+            params: Some((ksize, moltype)),
+            ..self
+        }))
+    }
+}
+
+fn load_sbt(
+    path: &Path,
+    _ksize: Option<usize>,
+    _moltype: Option<HashFunctions>,
+) -> Result<Option<(Database, usize)>> {
+    let sbt = match Sbt::from_path(path) {
+        Ok(sbt) => sbt,
+        Err(_) => return Ok(None),
+    };
+
+    let description = read_description(path)?;
+    Ok(Some((
+        Database {
+            index: Box::new(sbt),
+            path: path.to_string_lossy().into(),
+            locations: index_locations(path, &description),
+            params: index_params(&description),
+        },
+        0,
+    )))
+}
+
+fn load_linear_index(
+    path: &Path,
+    _ksize: Option<usize>,
+    _moltype: Option<HashFunctions>,
+) -> Result<Option<(Database, usize)>> {
+    let lindex = match LinearIndex::<Signature>::from_path(path) {
+        Ok(lindex) => lindex,
+        Err(_) => return Ok(None),
+    };
+
+    let description = read_description(path)?;
+    Ok(Some((
+        Database {
+            index: Box::new(lindex),
+            path: path.to_string_lossy().into(),
+            locations: index_locations(path, &description),
+            params: index_params(&description),
+        },
+        0,
+    )))
+}
+
+/// Load the signatures in a file matching `ksize` and `moltype`
+/// into an in-memory `LinearIndex`.
+fn load_signature_index(
+    path: &Path,
+    ksize: Option<usize>,
+    moltype: Option<HashFunctions>,
+) -> Result<Option<(Database, usize)>> {
+    let path_str: String = path.to_string_lossy().into();
+
+    // positions are the ones in the file, before splitting and selecting sketches
+    let mut sigs = Vec::new();
+    let mut locations = HashMap::new();
+    for (position, sig) in read_signatures(path)?.into_iter().enumerate() {
+        for sig in select_signatures(vec![sig], ksize, moltype) {
+            let location = Location {
+                position,
+                path: path_str.clone(),
+            };
+            locations.insert(sig.md5sum(), location);
+            sigs.push(sig);
+        }
+    }
+    if sigs.is_empty() {
+        return Ok(None);
+    }
+
+    let n_sigs = sigs.len();
+    let mut index = LinearIndex::<Signature>::builder().build();
+    index.batch_insert(sigs)?;
+
+    Ok(Some((
+        Database {
+            index: Box::new(index),
+            path: path_str,
+            locations,
+            params: ksize.zip(moltype),
+        },
+        n_sigs,
+    )))
+}
+
+pub fn find_location<'a>(
+    locations: &'a HashMap<String, Location>,
+    sig: &Signature,
+) -> Option<&'a Location> {
+    locations
+        .get(&sig.md5sum())
+        .or_else(|| locations.get(&sig.name()))
+}
+
+/// Parsed JSON description of an index.
+pub fn read_description(path: &Path) -> Result<serde_json::Value> {
+    let reader = io::BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Where each leaf of an index is stored, keyed by leaf name, filename and metadata.
+fn index_locations(path: &Path, description: &serde_json::Value) -> HashMap<String, Location> {
+    let storage = path.parent().unwrap_or_else(|| Path::new("")).join(
+        description["storage"]["args"]["path"]
+            .as_str()
+            .unwrap_or(""),
+    );
+
+    let leaves: Vec<(usize, &serde_json::Value)> = match &description["leaves"] {
+        serde_json::Value::Object(leaves) => leaves
+            .iter()
+            .filter_map(|(pos, leaf)| pos.parse().ok().map(|pos| (pos, leaf)))
+            .collect(),
+        serde_json::Value::Array(leaves) => leaves.iter().enumerate().collect(),
+        _ => vec![],
+    };
+
+    let mut locations = HashMap::new();
+    for (position, leaf) in leaves {
+        let location = Location {
+            position,
+            path: storage
+                .join(leaf["filename"].as_str().unwrap_or(""))
+                .to_string_lossy()
+                .into(),
+        };
+        for key in &[&leaf["name"], &leaf["filename"], &leaf["metadata"]] {
+            if let Some(key) = key.as_str().filter(|k| !k.is_empty()) {
+                locations
+                    .entry(key.into())
+                    .or_insert_with(|| location.clone());
+            }
+        }
+    }
+
+    locations
+}
+
+/// ksize and molecule type recorded in the index metadata by `write_index_metadata`.
+fn index_params(description: &serde_json::Value) -> Option<(usize, HashFunctions)> {
+    let ksize = description["metadata"]["ksize"].as_u64()? as usize;
+    let moltype = match description["metadata"]["moltype"].as_str()? {
+        "dna" => HashFunctions::murmur64_DNA,
+        "protein" => HashFunctions::murmur64_protein,
+        "dayhoff" => HashFunctions::murmur64_dayhoff,
+        "hp" => HashFunctions::murmur64_hp,
+        _ => return None,
+    };
+    Some((ksize, moltype))
+}
+
+/// Record the ksize and molecule type of all leaves in the index description,
+/// so databases can be checked against a query without loading any leaves.
+pub fn write_index_metadata(path: &Path, ksize: usize, moltype: HashFunctions) -> Result<()> {
+    let mut description = read_description(path)?;
+    description["metadata"] = serde_json::json!({
+        "ksize": ksize,
+        "moltype": moltype.to_string(),
+    });
+    serde_json::to_writer(io::BufWriter::new(File::create(path)?), &description)?;
+    Ok(())
+}
+
+/// Read all signatures from a file, either a JSON list of signatures
+/// or a single bare signature, possibly compressed.
+pub fn read_signatures(path: &Path) -> Result<Vec<Signature>> {
+    let (mut reader, _format) = niffler::from_path(path)?;
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer)?;
+
+    match serde_json::from_slice::<Vec<Signature>>(&buffer) {
+        Ok(sigs) => Ok(sigs),
+        Err(_) => Ok(vec![serde_json::from_slice::<Signature>(&buffer)?]),
+    }
+}
+
+/// Split signatures into one signature per sketch,
+/// keeping only the MinHash sketches matching `ksize` and `moltype`.
+pub fn select_signatures(
+    sigs: Vec<Signature>,
+    ksize: Option<usize>,
+    moltype: Option<HashFunctions>,
+) -> Vec<Signature> {
+    sigs.into_iter()
+        .flat_map(|sig| {
+            sig.sketches()
+                .into_iter()
+                .filter(|sk| !matches!(sk, Sketch::HyperLogLog(_)))
+                .filter(|sk| ksize.map(|k| k == sk.ksize()).unwrap_or(true))
+                .filter(|sk| moltype.map(|m| m == sk.hash_function()).unwrap_or(true))
+                .map(|sk| {
+                    let mut new_sig = sig.clone();
+                    new_sig.reset_sketches();
+                    new_sig.push(sk);
+                    new_sig
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
@@ -1,31 +1,45 @@
+use std::cell::RefCell;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
 use std::fs::File;
-use std::io;
-use std::path::Path;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use clap::{load_yaml, App};
+use clap::{load_yaml, App, ArgMatches};
 use eyre::{eyre, Result};
 use log::{error, info, warn, LevelFilter};
+use rayon::prelude::*;
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
 
 use sourmash::cmd::{prepare, ComputeParameters};
 
 use sourmash::encodings::HashFunctions;
-use sourmash::index::linear::LinearIndex;
-use sourmash::index::sbt::scaffold;
-use sourmash::index::search::{
-    search_minhashes, search_minhashes_containment, search_minhashes_find_best,
-};
+use sourmash::index::sbt::{scaffold, Node, SBT};
+use sourmash::index::search::{search_minhashes, search_minhashes_containment};
 use sourmash::index::storage::{FSStorage, Storage};
-use sourmash::index::{Comparable, Index, MHBT};
+use sourmash::index::{Comparable, Index, SigStore, MHBT};
 use sourmash::signature::{Signature, SigsTrait};
+use sourmash::sketch::minhash::{max_hash_for_scaled, KmerMinHash};
+use sourmash::sketch::nodegraph::Nodegraph;
 use sourmash::sketch::Sketch;
 
 mod cmd;
+mod database;
+mod sbt;
 
-use crate::cmd::{compute, CompareParameters};
+use crate::cmd::{
+    ani_from_containment, ani_from_jaccard, compare, compute, minhash_from_sig, CompareParameters,
+};
+use crate::database::{
+    find_location, read_description, read_signatures, select_signatures, write_index_metadata,
+    Database, DatabaseSignatures, Location,
+};
+use crate::sbt::SbtNode;
 
 // Original comment from ripgrep and why using jemalloc with musl is recommended:
 // https://github.com/BurntSushi/ripgrep/commit/03bf37ff4a29361c47843369f7d3dc5689b8fdac
@@ -51,88 +65,467 @@ use crate::cmd::{compute, CompareParameters};
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
-pub fn index(sig_files: Vec<&str>, storage: Rc<dyn Storage>, outfile: &str) -> Result<Indices> {
-    let mut index = MHBT::builder().storage(Some(Rc::clone(&storage))).build();
+/// Empty nodegraph for an internal SBT node,
+/// with the same parameters as the default factory of `MHBT`.
+fn internal_nodegraph() -> Nodegraph {
+    Nodegraph::with_tables(100_000, 4, 1)
+}
+
+/// SBT leaf holding `sig`, keyed by its md5sum like sourmash does, so leaves
+/// sharing a filename (like the ones from `compute --singleton`) are stored
+/// apart, and can be found in the index without loading them.
+fn sbt_leaf(sig: Signature) -> SigStore<Signature> {
+    let md5 = sig.md5sum();
+    SigStore::builder()
+        .filename(md5.clone())
+        .name(md5.clone())
+        .metadata(md5)
+        .storage(None)
+        .data(sig)
+        .build()
+}
+
+/// Build an SBT bottom-up with `leaves` in order at the bottom level,
+/// instead of inserting them one at a time. Internal nodes are built
+/// in parallel, one level at a time, from the hashes below them.
+fn build_sbt(
+    leaves: Vec<Signature>,
+    storage: Rc<dyn Storage>,
+    pool: &rayon::ThreadPool,
+) -> Result<MHBT> {
+    // smallest complete binary tree with room for all leaves
+    let mut depth = 0;
+    while (1 << depth) < leaves.len() {
+        depth += 1;
+    }
+    let first_leaf: u64 = (1 << depth) - 1;
+
+    let internal = pool.install(|| internal_nodes(&leaves, first_leaf, depth))?;
+
+    let nodes = internal
+        .into_iter()
+        .map(|(pos, nodegraph, min_n_below)| {
+            let mut metadata = HashMap::new();
+            metadata.insert("min_n_below".into(), min_n_below);
+            let node = Node::builder()
+                .filename(format!("internal.{}", pos))
+                .name(format!("internal.{}", pos))
+                .metadata(metadata)
+                .storage(Some(Rc::clone(&storage)))
+                .data(nodegraph)
+                .build();
+            (pos, node)
+        })
+        .collect();
+
+    let leaves = leaves
+        .into_iter()
+        .enumerate()
+        .map(|(i, sig)| (first_leaf + i as u64, sbt_leaf(sig)))
+        .collect();
+
+    Ok(SBT::builder()
+        .storage(Some(storage))
+        .nodes(nodes)
+        .leaves(leaves)
+        .build())
+}
+
+/// Nodegraph and `min_n_below` for each internal node above `leaves`,
+/// placed from `first_leaf` on at the bottom of a tree `depth` levels deep.
+fn internal_nodes(
+    leaves: &[Signature],
+    first_leaf: u64,
+    depth: usize,
+) -> Result<Vec<(u64, Nodegraph, u64)>> {
+    // position, hashes and min_n_below of the nodes in the current level
+    let mut level = leaves
+        .par_iter()
+        .enumerate()
+        .map(|(i, sig)| {
+            let mh = minhash_from_sig(sig)?;
+            let hashes: HashSet<u64> = mh.mins().into_iter().collect();
+            Ok((first_leaf + i as u64, hashes, mh.size() as u64))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut nodes = Vec::new();
+    for _ in 0..depth {
+        // siblings are next to each other, and each level starts with a left child
+        let parents: Vec<_> = level
+            .par_chunks(2)
+            .map(|children| {
+                let pos = (children[0].0 - 1) / 2;
+                let hashes: HashSet<u64> = children
+                    .iter()
+                    .flat_map(|(_, hashes, _)| hashes.iter().copied())
+                    .collect();
+                let min_n_below = children
+                    .iter()
+                    .map(|(_, _, n)| *n)
+                    .min()
+                    .unwrap_or(0)
+                    .max(1);
+
+                let mut nodegraph = internal_nodegraph();
+                for hash in &hashes {
+                    nodegraph.count(*hash);
+                }
+                ((pos, hashes, min_n_below), nodegraph)
+            })
+            .collect();
+
+        level = Vec::with_capacity(parents.len());
+        for ((pos, hashes, min_n_below), nodegraph) in parents {
+            nodes.push((pos, nodegraph, min_n_below));
+            level.push((pos, hashes, min_n_below));
+        }
+    }
+
+    Ok(nodes)
+}
 
-    for filename in sig_files {
-        // TODO: check for stdin? can also use get_input()?
+/// Entries of an SBT description section, keyed by position.
+fn description_entries(section: &serde_json::Value) -> BTreeMap<u64, serde_json::Value> {
+    match section {
+        serde_json::Value::Object(entries) => entries
+            .iter()
+            .filter_map(|(pos, entry)| pos.parse().ok().map(|pos| (pos, entry.clone())))
+            .collect(),
+        _ => BTreeMap::new(),
+    }
+}
 
-        let mut sig = Signature::from_path(filename)?;
+/// MinHash of the leaf described by `leaf`, loaded from `storage`.
+fn load_leaf_minhash(storage: &dyn Storage, leaf: &serde_json::Value) -> Result<KmerMinHash> {
+    let raw = storage.load(leaf["filename"].as_str().unwrap_or_default())?;
+    let sig = match serde_json::from_slice::<Vec<Signature>>(&raw) {
+        Ok(mut sigs) if !sigs.is_empty() => sigs.swap_remove(0),
+        _ => serde_json::from_slice(&raw)?,
+    };
+    minhash_from_sig(&sig)
+}
 
-        if sig.len() > 1 {
-            unimplemented!();
+/// Append `leaves` to the SBT described by `description`, writing the new
+/// description to `outfile`. Each leaf goes after the last one, like `SBT::insert`
+/// does: when a leaf is already where its parent goes, that leaf is moved down
+/// under a new internal node, next to the new one. So the tree stays balanced,
+/// and only the internal nodes above the new leaves change. They are saved under
+/// names the current description doesn't use, so no existing file is rewritten
+/// and readers of the current index are unaffected.
+fn append_sbt(
+    mut description: serde_json::Value,
+    leaves: &[Signature],
+    storage: Rc<dyn Storage>,
+    outfile: &str,
+    pool: &rayon::ThreadPool,
+) -> Result<()> {
+    if description["version"] != 5 || description["d"] != 2 {
+        return Err(eyre!("can only append to binary SBTs in version 5 format"));
+    }
+    let mut nodes = description_entries(&description["nodes"]);
+    let mut sbt_leaves = description_entries(&description["leaves"]);
+    let too_deep = || eyre!("can't append to {}, the SBT is too deep", outfile);
+
+    let minhashes = pool.install(|| {
+        leaves
+            .par_iter()
+            .map(minhash_from_sig)
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    // nodegraph and min_n_below of the internal nodes above the new leaves
+    let mut updated: BTreeMap<u64, (Nodegraph, u64)> = BTreeMap::new();
+
+    for (sig, mh) in leaves.iter().zip(&minhashes) {
+        // keyed by md5sum, like the leaves from `sbt_leaf`
+        let md5 = sig.md5sum();
+        let filename = storage.save(&md5, &serde_json::to_vec(&[sig])?)?;
+        let leaf = serde_json::json!({
+            "filename": filename,
+            "name": md5,
+            "metadata": md5,
+        });
+
+        let mut pos = match sbt_leaves.keys().next_back() {
+            Some(last) => last.checked_add(1).ok_or_else(too_deep)?,
+            None => {
+                sbt_leaves.insert(0, leaf);
+                continue;
+            }
         };
+        let parent = (pos - 1) / 2;
+
+        if let Some(moved) = sbt_leaves.remove(&parent) {
+            // the leaf in the way becomes the left child of a new internal node
+            let left = parent
+                .checked_mul(2)
+                .and_then(|pos| pos.checked_add(1))
+                .ok_or_else(too_deep)?;
+            pos = left.checked_add(1).ok_or_else(too_deep)?;
+
+            let moved_mh = load_leaf_minhash(storage.as_ref(), &moved)?;
+            let mut nodegraph = internal_nodegraph();
+            for hash in moved_mh.mins() {
+                nodegraph.count(hash);
+            }
+            updated.insert(parent, (nodegraph, moved_mh.size() as u64));
+            sbt_leaves.insert(left, moved);
+        }
+        sbt_leaves.insert(pos, leaf);
+
+        // every node above the new leaf gets its hashes, missing ones are created
+        let mut ancestor = Some(parent);
+        while let Some(node_pos) = ancestor {
+            if sbt_leaves.contains_key(&node_pos) {
+                return Err(eyre!(
+                    "can't append to {}, leaf {} has leaves below it",
+                    outfile,
+                    node_pos
+                ));
+            }
+
+            let (nodegraph, min_n_below) = match updated.entry(node_pos) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let loaded = match nodes.get(&node_pos) {
+                        Some(node) => {
+                            let raw =
+                                storage.load(node["filename"].as_str().unwrap_or_default())?;
+                            (
+                                Nodegraph::from_reader(&mut &raw[..])?,
+                                node["metadata"]["min_n_below"].as_u64().unwrap_or(1),
+                            )
+                        }
+                        None => (internal_nodegraph(), u64::MAX),
+                    };
+                    entry.insert(loaded)
+                }
+            };
+            for hash in mh.mins() {
+                nodegraph.count(hash);
+            }
+            *min_n_below = u64::min(*min_n_below, mh.size() as u64);
 
-        index.insert(sig.pop().unwrap())?;
+            ancestor = node_pos.checked_sub(1).map(|pos| pos / 2);
+        }
     }
 
-    // TODO: implement to_writer and use this?
-    //let mut output = get_output(outfile, CompressionFormat::No)?;
-    //index.to_writer(&mut output)?
+    let mut used: HashSet<String> = nodes
+        .values()
+        .filter_map(|node| node["filename"].as_str().map(String::from))
+        .collect();
+    for (pos, (nodegraph, min_n_below)) in &updated {
+        let mut name = format!("internal.{}", pos);
+        let mut suffix = 1;
+        while used.contains(&name) {
+            name = format!("internal.{}.{}", pos, suffix);
+            suffix += 1;
+        }
 
-    index.save_file(outfile, Some(storage))?;
+        let mut buffer = Vec::new();
+        nodegraph.save_to_writer(&mut buffer)?;
+        let filename = storage.save(&name, &buffer)?;
+        nodes.insert(
+            *pos,
+            serde_json::json!({
+                "filename": filename,
+                "name": name,
+                "metadata": {"min_n_below": (*min_n_below).max(1)},
+            }),
+        );
+        used.insert(name);
+    }
 
-    Ok(Indices::MHBT(index))
+    let section = |entries: BTreeMap<u64, serde_json::Value>| -> serde_json::Value {
+        entries
+            .into_iter()
+            .map(|(pos, entry)| (pos.to_string(), entry))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    };
+    description["nodes"] = section(nodes);
+    description["leaves"] = section(sbt_leaves);
+    serde_json::to_writer(io::BufWriter::new(File::create(outfile)?), &description)?;
 
-    /*
-      let mut lindex = LinearIndex::<Signature>::builder()
-          .storage(Rc::clone(&storage))
-          .build();
+    Ok(())
+}
+
+/// Build an SBT from the signatures in `sig_files`, after the `existing` leaves
+/// of the index being appended to (if any).
+pub fn index(
+    sig_files: &[PathBuf],
+    existing: Option<&Database>,
+    storage: Rc<dyn Storage>,
+    outfile: &str,
+    ksize: Option<usize>,
+    moltype: HashFunctions,
+    processes: usize,
+) -> Result<()> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(processes)
+        .build()?;
+
+    // parsing is the slow part, so files are read in parallel
+    // and signatures are selected in order afterwards
+    let file_sigs = pool.install(|| {
+        sig_files
+            .par_iter()
+            .map(|filename| read_signatures(filename))
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    // without --ksize, all leaves use the ksize of the first one
+    let mut ksize = ksize;
+    let mut n_duplicates = 0;
+    let mut n_existing = 0;
+    let mut md5s = HashSet::new();
+    let mut leaves = Vec::new();
+
+    for (filename, sigs) in sig_files.iter().zip(file_sigs) {
+        for sig in sigs {
+            let name = sig.name();
+
+            // leaves hold a single sketch
+            let leaf = match select_signatures(vec![sig], ksize, Some(moltype))
+                .into_iter()
+                .next()
+            {
+                Some(leaf) => leaf,
+                None => {
+                    warn!(
+                        "skipping {} from {}, no sketch matching {}",
+                        name,
+                        filename.display(),
+                        describe_selection(ksize, moltype)
+                    );
+                    continue;
+                }
+            };
 
-      for filename in sig_files {
-          // TODO: check for stdin? can also use get_input()?
+            // leaves are stored under their md5, so they can be checked without loading them
+            if existing.is_some_and(|db| db.locations.contains_key(&leaf.md5sum())) {
+                info!(
+                    "skipping {} from {}, already in {}",
+                    name,
+                    filename.display(),
+                    outfile
+                );
+                n_existing += 1;
+                continue;
+            }
+            if !md5s.insert(leaf.md5sum()) {
+                warn!(
+                    "skipping {} from {}, duplicate md5 {}",
+                    name,
+                    filename.display(),
+                    leaf.md5sum()
+                );
+                n_duplicates += 1;
+                continue;
+            }
 
-          let mut sig = Signature::from_path(filename)?;
+            if ksize.is_none() {
+                let k = leaf.sketches()[0].ksize();
+                info!("no --ksize selected, indexing k={} sketches", k);
+                ksize = Some(k);
+            }
+            leaves.push(leaf);
+        }
+    }
 
-          if sig.len() > 1 {
-              unimplemented!();
-          };
+    if existing.is_none() {
+        info!(
+            "indexed {} signatures from {} files, skipped {} duplicates",
+            leaves.len(),
+            sig_files.len(),
+            n_duplicates
+        );
+    } else {
+        info!(
+            "appended {} signatures from {} files to {}, skipped {} duplicates and {} already indexed",
+            leaves.len(),
+            sig_files.len(),
+            outfile,
+            n_duplicates,
+            n_existing
+        );
+    }
 
-          lindex.insert(sig.pop().unwrap())?;
-      }
+    if existing.is_some() && leaves.is_empty() && n_existing > 0 {
+        return Ok(());
+    }
+    let ksize = match ksize {
+        Some(k) if !leaves.is_empty() => k,
+        _ => {
+            return Err(eyre!(
+                "no signatures to index matching {}",
+                describe_selection(ksize, moltype)
+            ))
+        }
+    };
 
-      let mut index: MHBT = lindex.into();
+    // the description is written next to the output and moved in place at the end,
+    // so readers never see it half-written, even when appending to an index
+    let tmp_outfile = format!("{}.tmp", outfile);
+    if existing.is_some() {
+        let description = read_description(Path::new(outfile))?;
+        append_sbt(description, &leaves, storage, &tmp_outfile, &pool)?;
+    } else {
+        let mut index = build_sbt(leaves, Rc::clone(&storage), &pool)?;
 
-      // TODO: implement to_writer and use this?
-      //let mut output = get_output(outfile, CompressionFormat::No)?;
-      //index.to_writer(&mut output)?
+        // TODO: implement to_writer and use this?
+        //let mut output = get_output(outfile, CompressionFormat::No)?;
+        //index.to_writer(&mut output)?
 
-      index.save_file(outfile, Some(storage))?;
+        index.save_file(&tmp_outfile, Some(storage))?;
+    }
+    write_index_metadata(Path::new(&tmp_outfile), ksize, moltype)?;
+    std::fs::rename(&tmp_outfile, outfile)?;
 
-      Ok(Indices::MHBT(index))
-    */
+    Ok(())
 }
 
+#[derive(Clone)]
 struct Query<T> {
     data: T,
+    ksize: usize,
+    hash_function: HashFunctions,
 }
 
 impl Query<Signature> {
+    /// A query for the single sketch left in `data` by `select_signatures`,
+    /// keeping its parameters.
+    fn new(data: Signature) -> Result<Query<Signature>> {
+        let (ksize, hash_function) = match data.sketches().as_slice() {
+            [Sketch::MinHash(mh)] => (mh.ksize(), mh.hash_function()),
+            [Sketch::LargeMinHash(mh)] => (mh.ksize(), mh.hash_function()),
+            [Sketch::HyperLogLog(_)] => {
+                return Err(eyre!("HyperLogLog sketches can't be used as a query"))
+            }
+            sketches => {
+                return Err(eyre!(
+                    "a query needs exactly one sketch, found {}",
+                    sketches.len()
+                ))
+            }
+        };
+
+        Ok(Query {
+            data,
+            ksize,
+            hash_function,
+        })
+    }
+
     fn ksize(&self) -> u64 {
-        // TODO: select the correct signature
-        self.data.sketches()[0].ksize() as u64
+        self.ksize as u64
     }
 
     fn moltype(&self) -> String {
-        // TODO: this might panic
-        match &self.data.sketches()[0] {
-            Sketch::MinHash(mh) => {
-                if mh.is_protein() {
-                    "protein".into()
-                } else {
-                    "DNA".into()
-                }
-            }
-            Sketch::LargeMinHash(mh) => {
-                if mh.is_protein() {
-                    "protein".into()
-                } else {
-                    "DNA".into()
-                }
-            }
-            Sketch::HyperLogLog(_) => {
-                unimplemented!()
-            }
+        match self.hash_function {
+            HashFunctions::murmur64_DNA => "DNA".into(),
+            _ => self.hash_function.to_string(),
         }
     }
 
@@ -147,130 +540,505 @@ impl From<Query<Signature>> for Signature {
     }
 }
 
+fn describe_sketch(ksize: usize, moltype: HashFunctions) -> String {
+    match moltype {
+        HashFunctions::murmur64_DNA => format!("k={}, DNA", ksize),
+        _ => format!("k={}, {}", ksize, moltype),
+    }
+}
+
+/// Like `describe_sketch`, for a selection that may not have a ksize.
+fn describe_selection(ksize: Option<usize>, moltype: HashFunctions) -> String {
+    match (ksize, moltype) {
+        (Some(k), _) => describe_sketch(k, moltype),
+        (None, HashFunctions::murmur64_DNA) => "DNA".into(),
+        (None, _) => moltype.to_string(),
+    }
+}
+
+/// Load a query signature, selecting a single sketch by `ksize` and `moltype`.
+/// When more than one sketch is left, pick the only one compatible with the
+/// (ksize, molecule type) pairs returned by `available`.
 fn load_query_signature(
     query: &str,
     ksize: Option<usize>,
     moltype: Option<&str>,
-    scaled: Option<u64>,
+    available: &dyn Fn() -> Result<Vec<(usize, HashFunctions)>>,
 ) -> Result<Query<Signature>> {
+    let mut queries = load_query_signatures(query, ksize, moltype, available)?;
+    if queries.len() > 1 {
+        warn!(
+            "found {} query signatures in {}, using the first one",
+            queries.len(),
+            query
+        );
+    }
+    Ok(queries.swap_remove(0))
+}
+
+/// Load all signatures in a query file, at the ksize and molecule type
+/// selected as in `load_query_signature`.
+fn load_query_signatures(
+    query: &str,
+    ksize: Option<usize>,
+    moltype: Option<&str>,
+    available: &dyn Fn() -> Result<Vec<(usize, HashFunctions)>>,
+) -> Result<Vec<Query<Signature>>> {
     let moltype: Option<HashFunctions> = if let Some(mol) = moltype {
         Some(mol.try_into()?)
     } else {
         None
     };
 
-    let mut reader = io::BufReader::new(File::open(query)?);
-    let sigs = Signature::load_signatures(&mut reader, ksize, moltype, scaled)?;
+    let sigs = select_signatures(read_signatures(Path::new(query))?, ksize, moltype);
 
-    //dbg!(&sigs);
-    // TODO: what if we have more than one left?
-    let data = sigs[0].clone();
+    if sigs.is_empty() {
+        return Err(eyre!(
+            "no query signature found in {} for the given ksize/molecule type",
+            query
+        ));
+    }
 
-    Ok(Query { data })
-}
+    let sketch_params = |sig: &Signature| {
+        let sketch = &sig.sketches()[0];
+        (sketch.ksize(), sketch.hash_function())
+    };
 
-struct Database {
-    data: Indices,
-    path: String,
+    let mut candidates: Vec<(usize, HashFunctions)> = Vec::new();
+    for sig in &sigs {
+        let params = sketch_params(sig);
+        if !candidates.contains(&params) {
+            candidates.push(params);
+        }
+    }
+
+    let selected = if candidates.len() > 1 {
+        let available = available()?;
+
+        let compatible: Vec<&(usize, HashFunctions)> = candidates
+            .iter()
+            .filter(|params| available.contains(params))
+            .collect();
+
+        if compatible.len() != 1 {
+            let listing = candidates
+                .iter()
+                .map(|(k, m)| describe_sketch(*k, *m))
+                .collect::<Vec<_>>()
+                .join("; ");
+            return Err(eyre!(
+                "{} sketches in {} match the databases, select one with --ksize or a molecule type: {}",
+                if compatible.is_empty() { "no" } else { "multiple" },
+                query,
+                listing
+            ));
+        }
+
+        let (k, m) = *compatible[0];
+        info!(
+            "selected query sketch ({}) matching the databases",
+            describe_sketch(k, m)
+        );
+        (k, m)
+    } else {
+        candidates[0]
+    };
+
+    sigs.into_iter()
+        .filter(|sig| sketch_params(sig) == selected)
+        .map(Query::new)
+        .collect()
 }
 
-pub enum Indices {
-    MHBT(MHBT),
-    LinearIndex(LinearIndex<Signature>),
+/// Paths to load databases from, walking directories when `traverse` is set.
+fn database_files(path: &str, traverse: bool) -> Result<Vec<PathBuf>> {
+    if traverse && Path::new(path).is_dir() {
+        let extensions = [SIG_EXTENSIONS, SBT_EXTENSIONS].concat();
+        let mut files = Vec::new();
+        traverse_files(Path::new(path), &extensions, &mut files)?;
+        Ok(files)
+    } else {
+        Ok(vec![PathBuf::from(path)])
+    }
 }
 
-impl Index<'_> for Database {
-    type Item = Signature;
+/// Molecule type selected by the --protein/--dayhoff/--hp/--dna flags.
+fn moltype_from_args(cmd: &ArgMatches) -> HashFunctions {
+    if cmd.is_present("protein") {
+        HashFunctions::murmur64_protein
+    } else if cmd.is_present("dayhoff") {
+        HashFunctions::murmur64_dayhoff
+    } else if cmd.is_present("hp") {
+        HashFunctions::murmur64_hp
+    } else {
+        HashFunctions::murmur64_DNA
+    }
+}
 
-    fn find<F>(
-        &self,
-        search_fn: F,
-        sig: &Self::Item,
-        threshold: f64,
-    ) -> std::result::Result<Vec<&Self::Item>, sourmash::Error>
-    where
-        F: Fn(&dyn Comparable<Self::Item>, &Self::Item, f64) -> bool,
-    {
-        match &self.data {
-            Indices::MHBT(data) => data.find(search_fn, sig, threshold),
-            Indices::LinearIndex(data) => data.find(search_fn, sig, threshold),
+const SIG_EXTENSIONS: &[&str] = &[".sig", ".sig.gz"];
+const SBT_EXTENSIONS: &[&str] = &[".sbt.json"];
+
+/// Collect files under a directory ending in one of `extensions`, recursively.
+fn traverse_files(dir: &Path, extensions: &[&str], files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            traverse_files(&path, extensions, files)?;
+        } else if path
+            .to_str()
+            .map(|p| extensions.iter().any(|ext| p.ends_with(ext)))
+            .unwrap_or(false)
+        {
+            files.push(path);
         }
     }
+    Ok(())
+}
 
-    fn insert(&mut self, node: Self::Item) -> std::result::Result<(), sourmash::Error> {
-        match &mut self.data {
-            Indices::MHBT(data) => data.insert(node),
-            Indices::LinearIndex(data) => data.insert(node),
+/// Collect signature files under a directory, recursively.
+fn traverse_signatures(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    traverse_files(dir, SIG_EXTENSIONS, files)
+}
+
+fn load_signatures(
+    filenames: &[&str],
+    ksize: Option<usize>,
+    moltype: HashFunctions,
+    traverse: bool,
+) -> Result<Vec<Signature>> {
+    let mut paths = Vec::new();
+    for filename in filenames {
+        let path = Path::new(filename);
+        if traverse && path.is_dir() {
+            traverse_signatures(path, &mut paths)?;
+        } else {
+            paths.push(path.to_path_buf());
         }
     }
 
-    fn save<P: AsRef<Path>>(&self, path: P) -> std::result::Result<(), sourmash::Error> {
-        match &self.data {
-            Indices::MHBT(data) => data.save(path),
-            Indices::LinearIndex(data) => data.save(path),
+    let mut siglist = Vec::new();
+    for path in paths {
+        let sigs = select_signatures(read_signatures(&path)?, ksize, Some(moltype));
+
+        if sigs.is_empty() {
+            warn!(
+                "no signatures loaded at given ksize/molecule type from {}",
+                path.display()
+            );
         }
+        siglist.extend(sigs);
     }
 
-    fn load<P: AsRef<Path>>(_path: P) -> std::result::Result<(), sourmash::Error> {
-        unimplemented!();
+    info!("loaded {} signatures total.", siglist.len());
+
+    Ok(siglist)
+}
+
+/// Check that a database with `ksize` and `moltype` can be searched with `query_mh`.
+fn check_params(ksize: usize, moltype: HashFunctions, query_mh: &KmerMinHash) -> Result<()> {
+    if ksize != query_mh.ksize() {
+        return Err(eyre!(
+            "ksize mismatch: query uses k={}, database uses k={}",
+            query_mh.ksize(),
+            ksize
+        ));
+    }
+    if moltype != query_mh.hash_function() {
+        return Err(eyre!(
+            "molecule type mismatch: query is {}, database is {}",
+            query_mh.hash_function(),
+            moltype
+        ));
     }
+    Ok(())
+}
 
-    fn signatures(&self) -> Vec<Self::Item> {
-        match &self.data {
-            Indices::MHBT(data) => data.signatures(),
-            Indices::LinearIndex(data) => data.signatures(),
+/// Check that `db` can be searched with `query_mh`: same ksize, molecule
+/// type and seed, and scaled (required for containment) or num in both.
+fn check_compatible(db: &Database, query_mh: &KmerMinHash, containment: bool) -> Result<()> {
+    // parameters recorded in the index can be checked without loading any leaves
+    if let Some((ksize, moltype)) = db.params {
+        check_params(ksize, moltype, query_mh)?;
+    }
+
+    let db_mh = match db.template()? {
+        Some(mh) => mh,
+        None => return Ok(()),
+    };
+
+    check_params(db_mh.ksize(), db_mh.hash_function(), query_mh)?;
+    if db_mh.seed() != query_mh.seed() {
+        return Err(eyre!(
+            "seed mismatch: query uses {}, database uses {}",
+            query_mh.seed(),
+            db_mh.seed()
+        ));
+    }
+
+    let describe = |mh: &KmerMinHash| {
+        if mh.scaled() > 0 {
+            format!("scaled={}", mh.scaled())
+        } else {
+            format!("num={}", mh.num())
         }
+    };
+    if (db_mh.scaled() == 0) != (query_mh.scaled() == 0) {
+        return Err(eyre!(
+            "query uses {} but database uses {}",
+            describe(query_mh),
+            describe(&db_mh)
+        ));
     }
+    if containment && db_mh.scaled() == 0 {
+        return Err(eyre!(
+            "containment requires scaled signatures, database uses {}",
+            describe(&db_mh)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Database files found under one of the paths given on the command line,
+/// either just their paths or loaded without selecting any sketches.
+struct DatabasePath<T> {
+    path: String,
+    is_dir: bool,
+    files: Vec<T>,
+}
 
-    fn signature_refs(&self) -> Vec<&Self::Item> {
-        match &self.data {
-            Indices::MHBT(data) => data.signature_refs(),
-            Indices::LinearIndex(data) => data.signature_refs(),
+/// A database file with what `load_database_file` loaded from it.
+type LoadedFile = (PathBuf, Option<(Database, usize)>);
+
+/// Database files in `filenames`, walking directories when `traverse` is set.
+fn database_paths(filenames: &[&str], traverse: bool) -> Result<Vec<DatabasePath<PathBuf>>> {
+    filenames
+        .iter()
+        .map(|path| {
+            Ok(DatabasePath {
+                path: path.to_string(),
+                is_dir: traverse && Path::new(path).is_dir(),
+                files: database_files(path, traverse)?,
+            })
+        })
+        .collect()
+}
+
+/// Load a database file with all its sketches, warning
+/// and returning `None` if it can't be loaded.
+fn load_database_file(file: &Path) -> Option<(Database, usize)> {
+    match Database::from_path(file, None, None) {
+        Ok(Some(loaded)) => Some(loaded),
+        Ok(None) => {
+            warn!("no signatures loaded from {}", file.display());
+            None
+        }
+        Err(e) => {
+            warn!(
+                "couldn't load {} as an SBT or signature file: {}",
+                file.display(),
+                e
+            );
+            None
         }
     }
 }
 
-fn load_sbts_and_sigs(
-    filenames: &[&str],
-    query: &Query<Signature>,
-    _containment: bool,
-    traverse: bool,
-) -> Result<Vec<Database>> {
-    let mut dbs = Vec::default();
+/// Load every database in `filenames`, walking directories when `traverse` is set.
+/// Databases are loaded once, and selected for a query with `select_databases`.
+fn load_databases(filenames: &[&str], traverse: bool) -> Result<Vec<DatabasePath<LoadedFile>>> {
+    Ok(database_paths(filenames, traverse)?
+        .into_iter()
+        .map(|path| DatabasePath {
+            files: path
+                .files
+                .into_iter()
+                .map(|file| {
+                    let loaded = load_database_file(&file);
+                    (file, loaded)
+                })
+                .collect(),
+            path: path.path,
+            is_dir: path.is_dir,
+        })
+        .collect())
+}
 
-    let _ksize = query.ksize();
-    let _moltype = query.moltype();
+/// (ksize, molecule type) of all sketches available in a database.
+fn sketch_params(db: &Database) -> Result<Vec<(usize, HashFunctions)>> {
+    if let Some(params) = db.params {
+        return Ok(vec![params]);
+    }
 
-    let n_signatures = 0;
-    let mut n_databases = 0;
+    // indices share the same parameters, signature files may not
+    let sigs = if db.is_linear() {
+        db.signatures()
+    } else {
+        db.first_signature()?.into_iter().collect()
+    };
+    Ok(select_signatures(sigs, None, None)
+        .iter()
+        .map(|sig| {
+            let sketch = &sig.sketches()[0];
+            (sketch.ksize(), sketch.hash_function())
+        })
+        .collect())
+}
 
-    for path in filenames {
-        if traverse && Path::new(path).is_dir() {
-            continue;
+/// (ksize, molecule type) of all sketches available in the databases.
+fn database_params(databases: &[DatabasePath<LoadedFile>]) -> Result<Vec<(usize, HashFunctions)>> {
+    let mut params = Vec::new();
+    for (_, loaded) in databases.iter().flat_map(|path| &path.files) {
+        if let Some((db, _)) = loaded {
+            params.extend(sketch_params(db)?);
         }
+    }
+    Ok(params)
+}
 
-        if let Ok(data) = MHBT::from_path(path) {
-            // TODO: check compatible
-            dbs.push(Database {
-                data: Indices::MHBT(data),
-                path: String::from(*path),
-            });
-            info!("loaded SBT {}", path);
-            n_databases += 1;
-            continue;
-        } else if let Ok(data) = LinearIndex::<Signature>::from_path(path) {
-            // TODO: check compatible
-            dbs.push(Database {
-                data: Indices::LinearIndex(data),
-                path: String::from(*path),
-            });
-            info!("loaded LinearIndex {}", path);
-            n_databases += 1;
-            continue;
+/// A database file loaded by `preload_database_files`,
+/// in a form that can be sent to the task searching it.
+enum PreloadedFile {
+    /// couldn't be loaded, and was already reported
+    Failed,
+    /// a linear database, with the number of signatures loaded from a signature file
+    Signatures(DatabaseSignatures, usize),
+    /// an index, loaded again by the task searching it (its leaves are read lazily)
+    Index,
+}
+
+/// A preloaded file, with the (ksize, molecule type) of all the sketches available in it.
+type PreloadedParams = (PreloadedFile, Vec<(usize, HashFunctions)>);
+
+/// Load database files in parallel, as taken by `search_database_files`.
+fn preload_database_files(paths: &[DatabasePath<PathBuf>]) -> Result<Vec<PreloadedParams>> {
+    let files: Vec<&PathBuf> = paths.iter().flat_map(|path| &path.files).collect();
+    files
+        .par_iter()
+        .map(|file| {
+            let (db, n_sigs) = match load_database_file(file) {
+                Some(loaded) => loaded,
+                None => return Ok((PreloadedFile::Failed, Vec::new())),
+            };
+            let params = sketch_params(&db)?;
+            let preloaded = match db.into_signatures() {
+                Some(sigs) => PreloadedFile::Signatures(sigs, n_sigs),
+                None => PreloadedFile::Index,
+            };
+            Ok((preloaded, params))
+        })
+        .collect()
+}
+
+/// What happened to a database file loaded for a query.
+#[derive(Clone, Copy)]
+enum LoadStatus {
+    /// a signature file, with the number of signatures kept
+    Signatures(usize),
+    Index,
+    Skipped,
+    Incompatible,
+}
+
+/// A database selected by `select_database`.
+struct SelectedDatabase {
+    db: Database,
+    /// whether each query can be searched in `db`
+    compatible: Vec<bool>,
+}
+
+/// Keep the part of a loaded database that can be searched with the queries,
+/// given by name and MinHash. The queries share the same ksize and molecule
+/// type (see `check_query_params`), but may still be incompatible with
+/// different databases.
+fn select_database(
+    file: &Path,
+    loaded: Option<(Database, usize)>,
+    queries: &[(String, KmerMinHash)],
+    containment: bool,
+) -> Result<(LoadStatus, Option<SelectedDatabase>)> {
+    let (db, n_sigs) = match loaded {
+        Some(loaded) => loaded,
+        None => return Ok((LoadStatus::Skipped, None)),
+    };
+
+    let query_mh = &queries[0].1;
+    let db = match db.select(query_mh.ksize(), query_mh.hash_function())? {
+        Some(db) => db,
+        None => {
+            warn!(
+                "no signatures loaded at given ksize/molecule type from {}",
+                file.display()
+            );
+            return Ok((LoadStatus::Skipped, None));
         }
+    };
+
+    let mut compatible = Vec::new();
+    for (name, query_mh) in queries {
+        match check_compatible(&db, query_mh, containment) {
+            Ok(()) => compatible.push(true),
+            Err(e) => {
+                if queries.len() > 1 {
+                    warn!(
+                        "skipping {} for query {}, incompatible: {}",
+                        file.display(),
+                        name,
+                        e
+                    );
+                } else {
+                    warn!(
+                        "skipping {}, incompatible with query: {}",
+                        file.display(),
+                        e
+                    );
+                }
+                compatible.push(false);
+            }
+        }
+    }
+    if !compatible.contains(&true) {
+        return Ok((LoadStatus::Incompatible, None));
+    }
+
+    let status = if n_sigs > 0 {
+        LoadStatus::Signatures(db.signature_refs().len())
+    } else {
+        LoadStatus::Index
+    };
+    Ok((status, Some(SelectedDatabase { db, compatible })))
+}
+
+/// Report how many files were loaded from a directory.
+fn report_directory(path: &str, statuses: &[LoadStatus]) {
+    let n_loaded = statuses
+        .iter()
+        .filter(|status| matches!(status, LoadStatus::Signatures(_) | LoadStatus::Index))
+        .count();
+    info!(
+        "loaded {} files from {}, skipped {}",
+        n_loaded,
+        path,
+        statuses.len() - n_loaded
+    );
+}
 
-        // TODO: load sig, need to change Database
-        // IDEA: put sig into a LinearIndex, and replace Database with a Box<dyn Index>?
+/// Report the databases loaded for `query_mh`, failing if there are none.
+fn report_loaded(statuses: &[LoadStatus], query_mh: &KmerMinHash, paths: &[String]) -> Result<()> {
+    let mut n_signatures = 0;
+    let mut n_databases = 0;
+    let mut n_incompatible = 0;
+    for status in statuses {
+        match status {
+            LoadStatus::Signatures(n) => n_signatures += n,
+            LoadStatus::Index => n_databases += 1,
+            LoadStatus::Skipped => (),
+            LoadStatus::Incompatible => n_incompatible += 1,
+        }
     }
 
     if n_signatures > 0 && n_databases > 0 {
@@ -282,17 +1050,88 @@ fn load_sbts_and_sigs(
         info!("loaded {} signatures.", n_signatures);
     } else if n_databases > 0 {
         info!("loaded {} databases.", n_databases);
+    } else if n_incompatible > 0 {
+        return Err(eyre!(
+            "no databases compatible with the query ({}) in {:#?}",
+            describe_sketch(query_mh.ksize(), query_mh.hash_function()),
+            paths
+        ));
     } else {
-        return Err(eyre!("Couldn't load any databases from {:#?}", filenames));
+        return Err(eyre!("Couldn't load any databases from {:#?}", paths));
+    }
+
+    Ok(())
+}
+
+/// Fail if one of the queries can't be searched in any of the selected databases.
+/// `compatible` has the queries each selected database can be searched with.
+fn check_selected(queries: &[(String, KmerMinHash)], compatible: &[&[bool]]) -> Result<()> {
+    for (i, (name, query_mh)) in queries.iter().enumerate() {
+        if !compatible.iter().any(|db| db[i]) {
+            return Err(eyre!(
+                "no databases compatible with query {} ({})",
+                name,
+                describe_sketch(query_mh.ksize(), query_mh.hash_function())
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Name and MinHash of each query, as taken by `select_database`.
+fn query_minhashes(queries: &[Query<Signature>]) -> Result<Vec<(String, KmerMinHash)>> {
+    queries
+        .iter()
+        .map(|query| Ok((query.name(), minhash_from_sig(&query.data)?)))
+        .collect()
+}
+
+/// Select the databases loaded by `load_databases` that can be searched with
+/// at least one of the queries.
+fn select_databases(
+    databases: Vec<DatabasePath<LoadedFile>>,
+    queries: &[Query<Signature>],
+    containment: bool,
+) -> Result<Vec<SelectedDatabase>> {
+    let queries = query_minhashes(queries)?;
+    let paths: Vec<String> = databases.iter().map(|path| path.path.clone()).collect();
+
+    let mut dbs = Vec::new();
+    let mut statuses = Vec::new();
+    for path in databases {
+        let mut path_statuses = Vec::new();
+        for (file, loaded) in path.files {
+            let (status, db) = select_database(&file, loaded, &queries, containment)?;
+            path_statuses.push(status);
+            dbs.extend(db);
+        }
+
+        if path.is_dir {
+            report_directory(&path.path, &path_statuses);
+        }
+        statuses.extend(path_statuses);
     }
 
+    report_loaded(&statuses, &queries[0].1, &paths)?;
+
     Ok(dbs)
 }
 
 struct Results {
     similarity: f64,
+    ani: f64,
     match_sig: Signature,
     db: String,
+    query_name: String,
+    query_md5: String,
+    ksize: usize,
+    moltype: String,
+    scaled: u64,
+    num: u32,
+    intersect_bp: Option<u64>,
+    containment: f64,
+    max_containment: f64,
+    location: Option<Location>,
 }
 
 impl Serialize for Results {
@@ -300,57 +1139,910 @@ impl Serialize for Results {
     where
         S: Serializer,
     {
-        let mut partial = serializer.serialize_struct("Results", 4)?;
+        let mut partial = serializer.serialize_struct("Results", 16)?;
         partial.serialize_field("similarity", &self.similarity)?;
         partial.serialize_field("name", &self.match_sig.name())?;
         partial.serialize_field("filename", &self.db)?;
         partial.serialize_field("md5", &self.match_sig.md5sum())?;
+        partial.serialize_field("ani", &self.ani)?;
+        partial.serialize_field("query_name", &self.query_name)?;
+        partial.serialize_field("query_md5", &self.query_md5)?;
+        partial.serialize_field("ksize", &self.ksize)?;
+        partial.serialize_field("moltype", &self.moltype)?;
+        partial.serialize_field("scaled", &self.scaled)?;
+        partial.serialize_field("num", &self.num)?;
+        partial.serialize_field("intersect_bp", &self.intersect_bp)?;
+        partial.serialize_field("containment", &self.containment)?;
+        partial.serialize_field("max_containment", &self.max_containment)?;
+        partial.serialize_field(
+            "match_file",
+            &self.location.as_ref().map(|l| l.path.as_str()),
+        )?;
+        partial.serialize_field(
+            "match_position",
+            &self.location.as_ref().map(|l| l.position),
+        )?;
         partial.end()
     }
 }
 
-fn search_databases(
-    query: Query<Signature>,
+/// Options of a search, shared by all queries.
+struct SearchParams {
+    threshold: f64,
+    containment: bool,
+    best_only: bool,
+    ignore_abundance: bool,
+    scaled: Option<u64>,
+}
+
+/// Search `query` in `databases`, reporting matches above `threshold` (the bits
+/// of an `f64`). With --best-only the threshold is raised to the best score found
+/// so far, and can be shared between searches of the same query running concurrently.
+fn search_databases(
+    query: Query<Signature>,
+    databases: &[&Database],
+    threshold: &AtomicU64,
+    params: &SearchParams,
+) -> Result<Vec<Results>> {
+    let SearchParams {
+        containment,
+        best_only,
+        ignore_abundance,
+        scaled,
+        ..
+    } = *params;
+    let mut results = Vec::default();
+
+    let search_fn = if containment {
+        search_minhashes_containment
+    } else {
+        search_minhashes
+    };
+    let ksize = query.ksize() as usize;
+    let query_sig: Signature = query.into();
+    let mut query_mh = minhash_from_sig(&query_sig)?;
+
+    if let Some(scaled) = scaled {
+        if query_mh.scaled() == 0 {
+            warn!("query signature uses num, ignoring --scaled {}", scaled);
+        } else if scaled < query_mh.scaled() {
+            warn!(
+                "can't downsample query from scaled={} to scaled={}, keeping scaled={}",
+                query_mh.scaled(),
+                scaled,
+                query_mh.scaled()
+            );
+        } else if scaled > query_mh.scaled() {
+            info!(
+                "downsampling query from scaled={} to scaled={}",
+                query_mh.scaled(),
+                scaled
+            );
+            query_mh = downsample_to_scaled(query_mh, scaled)?;
+        }
+    }
+
+    // With --best-only the threshold is raised to the best score found so far,
+    // so the remaining databases (and SBT subtrees) are pruned more aggressively.
+    // This is only safe when the scores used for pruning are the same as the
+    // ones used for ranking, which isn't true for containment (pruning uses
+    // the containment of the match) or abundance-weighted similarity.
+    let tighten = best_only && !containment && (ignore_abundance || !query_mh.track_abundance());
+
+    for db in databases {
+        let db_threshold = load_threshold(threshold);
+        let db_scaled = db.template()?.map(|mh| mh.scaled()).unwrap_or(0);
+
+        let common_scaled = if query_mh.scaled() > 0 && db_scaled > query_mh.scaled() {
+            warn!(
+                "query uses scaled={} but {} uses scaled={}, downsampling query to scaled={}",
+                query_mh.scaled(),
+                db.path,
+                db_scaled,
+                db_scaled
+            );
+            db_scaled
+        } else {
+            query_mh.scaled()
+        };
+        let db_query_mh = downsample_to_scaled(query_mh.clone(), common_scaled)?;
+        if db_query_mh.size() == 0 {
+            warn!(
+                "query has no hashes left at scaled={}, skipping {}",
+                common_scaled, db.path
+            );
+            continue;
+        }
+
+        let candidates: Vec<Signature> = if db.is_linear() {
+            // scoring every signature is what a LinearIndex search does anyway,
+            // and it can be done in parallel below
+            db.signatures()
+        } else if common_scaled == db_scaled || db_scaled == 0 {
+            let db_query = signature_with_minhash(&query_sig, db_query_mh.clone());
+            let matches = if tighten {
+                // leaves raise the threshold for the rest of the tree as soon as they are scored
+                db.find_nodes(
+                    |node: SbtNode, query: &Signature, _threshold: f64| {
+                        let score = node.comparable().similarity(query);
+                        if score <= load_threshold(threshold) {
+                            return false;
+                        }
+                        if let SbtNode::Leaf(_) = node {
+                            raise_threshold(threshold, score);
+                        }
+                        true
+                    },
+                    &db_query,
+                    db_threshold,
+                )?
+            } else {
+                db.find(search_fn, &db_query, db_threshold)?
+            };
+            matches.into_iter().cloned().collect()
+        } else {
+            // the database signatures need to be downsampled too,
+            // so they can't be compared inside the index
+            warn!(
+                "{} uses scaled={}, downsampling its signatures to scaled={}",
+                db.path, db_scaled, common_scaled
+            );
+            db.signatures()
+        };
+
+        let db_path = &db.path;
+        let locations = &db.locations;
+        let query_name = query_sig.name();
+        let query_md5 = query_sig.md5sum();
+
+        let db_results = candidates
+            .into_par_iter()
+            .map(|dataset| -> Result<Option<Results>> {
+                let mut match_mh = minhash_from_sig(&dataset)?;
+                if common_scaled > 0 {
+                    match_mh = downsample_to_scaled(match_mh, common_scaled)?;
+                }
+
+                // nothing can be shared with an empty match, and its scores are undefined
+                if match_mh.size() == 0 {
+                    return Ok(None);
+                }
+
+                let common = db_query_mh.count_common(&match_mh, false)?;
+                let query_containment = common as f64 / db_query_mh.size() as f64;
+                let max_containment =
+                    common as f64 / usize::min(db_query_mh.size(), match_mh.size()) as f64;
+
+                let (similarity, ani) = if containment {
+                    (
+                        query_containment,
+                        ani_from_containment(query_containment, ksize),
+                    )
+                } else {
+                    // angular similarity is used when both track abundance,
+                    // but ANI can only be estimated from Jaccard
+                    let similarity = db_query_mh.similarity(&match_mh, ignore_abundance, false)?;
+                    let jaccard = db_query_mh.jaccard(&match_mh)?;
+                    (similarity, ani_from_jaccard(jaccard, ksize))
+                };
+
+                if similarity < db_threshold {
+                    return Ok(None);
+                }
+
+                Ok(Some(Results {
+                    similarity,
+                    ani,
+                    db: db_path.clone(),
+                    query_name: query_name.clone(),
+                    query_md5: query_md5.clone(),
+                    ksize: match_mh.ksize(),
+                    moltype: match_mh.hash_function().to_string(),
+                    scaled: match_mh.scaled(),
+                    num: match_mh.num(),
+                    intersect_bp: if common_scaled > 0 {
+                        Some(common * common_scaled)
+                    } else {
+                        None
+                    },
+                    containment: query_containment,
+                    max_containment,
+                    location: find_location(locations, &dataset).cloned(),
+                    match_sig: dataset,
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        results.extend(db_results.into_iter().flatten());
+
+        if tighten {
+            if let Some(max) = results.iter().map(|r| r.similarity).reduce(f64::max) {
+                raise_threshold(threshold, max);
+            }
+        }
+    }
+
+    // stable sort, so ties keep the first match found
+    results.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    if best_only {
+        results.truncate(1);
+    }
+    Ok(results)
+}
+
+/// Threshold shared by `search_databases`.
+fn load_threshold(threshold: &AtomicU64) -> f64 {
+    f64::from_bits(threshold.load(Ordering::Relaxed))
+}
+
+/// Raise a shared threshold to `score`. Scores are never negative,
+/// and non-negative floats are ordered the same as their bits.
+fn raise_threshold(threshold: &AtomicU64, score: f64) {
+    threshold.fetch_max(score.to_bits(), Ordering::Relaxed);
+}
+
+/// What a task of `search_database_files` found in a database file.
+struct FileResults {
+    /// index of the command line path the file was found under
+    path: usize,
+    status: LoadStatus,
+    /// whether each query could be searched in the file
+    compatible: Vec<bool>,
+    /// results for each query
+    results: Vec<Vec<Results>>,
+}
+
+/// Search database files concurrently, returning the results for each query.
+/// Indices can't be shared between threads, so each file is loaded in its own task
+/// (unless it was already loaded by `preload_database_files`), with the same checks
+/// and reports as `load_databases` and `select_databases`.
+fn search_database_files(
+    paths: &[DatabasePath<PathBuf>],
+    preloaded: Option<Vec<PreloadedFile>>,
+    queries: &[Query<Signature>],
+    params: &SearchParams,
+) -> Result<Vec<Vec<Results>>> {
+    let query_mhs = query_minhashes(queries)?;
+    let thresholds: Vec<AtomicU64> = queries
+        .iter()
+        .map(|_| AtomicU64::new(params.threshold.to_bits()))
+        .collect();
+
+    let files: Vec<(usize, &PathBuf)> = paths
+        .iter()
+        .enumerate()
+        .flat_map(|(i, path)| path.files.iter().map(move |file| (i, file)))
+        .collect();
+    let preloaded: Vec<Option<PreloadedFile>> = match preloaded {
+        Some(preloaded) => preloaded.into_iter().map(Some).collect(),
+        None => files.iter().map(|_| None).collect(),
+    };
+
+    let per_file = files
+        .into_par_iter()
+        .zip(preloaded)
+        .map(|((path, file), preloaded)| -> Result<FileResults> {
+            let loaded = match preloaded {
+                Some(PreloadedFile::Failed) => None,
+                Some(PreloadedFile::Signatures(sigs, n_sigs)) => {
+                    Some((Database::from_signatures(sigs)?, n_sigs))
+                }
+                Some(PreloadedFile::Index) | None => load_database_file(file),
+            };
+            let (status, selected) = select_database(file, loaded, &query_mhs, params.containment)?;
+            let selected = match selected {
+                Some(selected) => selected,
+                None => {
+                    return Ok(FileResults {
+                        path,
+                        status,
+                        compatible: vec![false; queries.len()],
+                        results: Vec::new(),
+                    })
+                }
+            };
+
+            let results = queries
+                .iter()
+                .zip(&thresholds)
+                .zip(&selected.compatible)
+                .map(|((query, threshold), compatible)| {
+                    if !compatible {
+                        return Ok(Vec::new());
+                    }
+                    search_databases(query.clone(), &[&selected.db], threshold, params)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(FileResults {
+                path,
+                status,
+                compatible: selected.compatible,
+                results,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for (i, path) in paths.iter().enumerate() {
+        if path.is_dir {
+            let statuses: Vec<LoadStatus> = per_file
+                .iter()
+                .filter(|file| file.path == i)
+                .map(|file| file.status)
+                .collect();
+            report_directory(&path.path, &statuses);
+        }
+    }
+    let statuses: Vec<LoadStatus> = per_file.iter().map(|file| file.status).collect();
+    let names: Vec<String> = paths.iter().map(|path| path.path.clone()).collect();
+    report_loaded(&statuses, &query_mhs[0].1, &names)?;
+    let compatible: Vec<&[bool]> = per_file
+        .iter()
+        .map(|file| file.compatible.as_slice())
+        .collect();
+    check_selected(&query_mhs, &compatible)?;
+
+    let mut merged: Vec<Vec<Results>> = queries.iter().map(|_| Vec::new()).collect();
+    for file in per_file {
+        for (query_results, results) in merged.iter_mut().zip(file.results) {
+            query_results.extend(results);
+        }
+    }
+
+    for results in &mut merged {
+        results.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        if params.best_only {
+            results.truncate(1);
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Copy of `sig` with `mh` as its only sketch.
+fn signature_with_minhash(sig: &Signature, mh: KmerMinHash) -> Signature {
+    let mut new_sig = sig.clone();
+    new_sig.reset_sketches();
+    new_sig.push(Sketch::MinHash(mh));
+    new_sig
+}
+
+struct PrefetchResult {
+    intersect_bp: u64,
+    jaccard: f64,
+    max_containment: f64,
+    f_query_match: f64,
+    f_match_query: f64,
+    match_sig: Signature,
+    match_bp: u64,
+    db: String,
+    query_name: String,
+    query_filename: String,
+    query_md5: String,
+    query_bp: u64,
+}
+
+impl Serialize for PrefetchResult {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut partial = serializer.serialize_struct("PrefetchResult", 13)?;
+        partial.serialize_field("intersect_bp", &self.intersect_bp)?;
+        partial.serialize_field("jaccard", &self.jaccard)?;
+        partial.serialize_field("max_containment", &self.max_containment)?;
+        partial.serialize_field("f_query_match", &self.f_query_match)?;
+        partial.serialize_field("f_match_query", &self.f_match_query)?;
+        partial.serialize_field("match_filename", &self.db)?;
+        partial.serialize_field("match_name", &self.match_sig.name())?;
+        partial.serialize_field("match_md5", &self.match_sig.md5sum())?;
+        partial.serialize_field("match_bp", &self.match_bp)?;
+        partial.serialize_field("query_filename", &self.query_filename)?;
+        partial.serialize_field("query_name", &self.query_name)?;
+        partial.serialize_field("query_md5", &self.query_md5)?;
+        partial.serialize_field("query_bp", &self.query_bp)?;
+        partial.end()
+    }
+}
+
+/// Find every dataset sharing at least `threshold_bp` with the query,
+/// calling `f` for each match as soon as it is found.
+fn prefetch_databases<F>(
+    query: &Signature,
+    databases: &[Database],
+    threshold_bp: u64,
+    mut f: F,
+) -> Result<usize>
+where
+    F: FnMut(PrefetchResult) -> Result<()>,
+{
+    let query_mh = minhash_from_sig(query)?;
+    if query_mh.scaled() == 0 {
+        return Err(eyre!(
+            "prefetch requires a query signature built with --scaled"
+        ));
+    }
+
+    let mut n_matches = 0;
+    for db in databases {
+        let candidates: Vec<Signature> = if db.is_linear() {
+            db.signatures()
+        } else {
+            // nodes only hold hashes below the database max_hash,
+            // so the query is compared at the larger of both scaled values
+            let db_scaled = db.template()?.map(|mh| mh.scaled()).unwrap_or(0);
+            let scaled = u64::max(query_mh.scaled(), db_scaled);
+            let db_query_mh = downsample_to_scaled(query_mh.clone(), scaled)?;
+            if db_query_mh.size() == 0 {
+                continue;
+            }
+
+            // a match needs at least `min_common` hashes in common with the query,
+            // and `find` keeps nodes with a containment strictly above the threshold
+            let min_common = threshold_bp.div_ceil(scaled);
+            let threshold = min_common.saturating_sub(1) as f64 / db_query_mh.size() as f64;
+
+            // leaf containment is relative to the leaf, not the query,
+            // so leaves are all kept and checked below
+            let db_query = signature_with_minhash(query, db_query_mh);
+            db.find_nodes(
+                |node: SbtNode, query: &Signature, threshold: f64| match node {
+                    SbtNode::Leaf(_) => true,
+                    SbtNode::Internal(node) => node.containment(query) > threshold,
+                },
+                &db_query,
+                threshold,
+            )?
+            .into_iter()
+            .cloned()
+            .collect()
+        };
+
+        for dataset in candidates {
+            let match_mh = minhash_from_sig(&dataset)?;
+            if match_mh.scaled() == 0 {
+                warn!("skipping {}: not built with --scaled", dataset.name());
+                continue;
+            }
+
+            let scaled = u64::max(query_mh.scaled(), match_mh.scaled());
+            let common = query_mh.count_common(&match_mh, true)?;
+            if common == 0 || common * scaled < threshold_bp {
+                continue;
+            }
+
+            let query_size = if query_mh.scaled() == scaled {
+                query_mh.size()
+            } else {
+                downsample_to_scaled(query_mh.clone(), scaled)?.size()
+            } as u64;
+            let match_size = if match_mh.scaled() == scaled {
+                match_mh.size()
+            } else {
+                downsample_to_scaled(match_mh, scaled)?.size()
+            } as u64;
+
+            f(PrefetchResult {
+                intersect_bp: common * scaled,
+                jaccard: common as f64 / (query_size + match_size - common) as f64,
+                max_containment: common as f64 / u64::min(query_size, match_size) as f64,
+                f_query_match: common as f64 / query_size as f64,
+                f_match_query: common as f64 / match_size as f64,
+                match_sig: dataset,
+                match_bp: match_size * scaled,
+                db: db.path.clone(),
+                query_name: query.name(),
+                query_filename: query.filename(),
+                query_md5: query.md5sum(),
+                query_bp: query_size * scaled,
+            })?;
+            n_matches += 1;
+        }
+    }
+
+    Ok(n_matches)
+}
+
+struct GatherResult {
+    intersect_bp: u64,
+    f_orig_query: f64,
+    f_match: f64,
+    f_unique_to_query: f64,
+    f_unique_weighted: f64,
+    average_abund: Option<f64>,
+    median_abund: Option<f64>,
+    std_abund: Option<f64>,
+    ani: f64,
+    match_sig: Signature,
+    db: String,
+    unique_intersect_bp: u64,
+    gather_result_rank: usize,
+    remaining_bp: u64,
+}
+
+impl Serialize for GatherResult {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut partial = serializer.serialize_struct("GatherResult", 15)?;
+        partial.serialize_field("intersect_bp", &self.intersect_bp)?;
+        partial.serialize_field("f_orig_query", &self.f_orig_query)?;
+        partial.serialize_field("f_match", &self.f_match)?;
+        partial.serialize_field("f_unique_to_query", &self.f_unique_to_query)?;
+        partial.serialize_field("f_unique_weighted", &self.f_unique_weighted)?;
+        partial.serialize_field("average_abund", &self.average_abund)?;
+        partial.serialize_field("median_abund", &self.median_abund)?;
+        partial.serialize_field("std_abund", &self.std_abund)?;
+        partial.serialize_field("name", &self.match_sig.name())?;
+        partial.serialize_field("filename", &self.db)?;
+        partial.serialize_field("md5", &self.match_sig.md5sum())?;
+        partial.serialize_field("ani", &self.ani)?;
+        partial.serialize_field("unique_intersect_bp", &self.unique_intersect_bp)?;
+        partial.serialize_field("gather_result_rank", &self.gather_result_rank)?;
+        partial.serialize_field("remaining_bp", &self.remaining_bp)?;
+        partial.end()
+    }
+}
+
+fn downsample_to_scaled(mh: KmerMinHash, scaled: u64) -> Result<KmerMinHash> {
+    if mh.scaled() == scaled {
+        Ok(mh)
+    } else {
+        Ok(mh.downsample_max_hash(max_hash_for_scaled(scaled))?)
+    }
+}
+
+fn gather_databases(
+    query: Query<Signature>,
+    databases: &[Database],
+    threshold_bp: u64,
+    ignore_abundance: bool,
+) -> Result<Vec<GatherResult>> {
+    let ksize = query.ksize() as usize;
+    let query_sig: Signature = query.into();
+
+    let mut orig_query = minhash_from_sig(&query_sig)?;
+    if orig_query.scaled() == 0 {
+        return Err(eyre!(
+            "gather requires a query signature built with --scaled"
+        ));
+    }
+
+    // only datasets overlapping the query above the threshold can ever be selected
+    let mut candidates = Vec::new();
+    prefetch_databases(&query_sig, databases, threshold_bp, |pr| {
+        let mh = minhash_from_sig(&pr.match_sig)?;
+        candidates.push((pr.match_sig, mh, pr.db));
+        Ok(())
+    })?;
+
+    // all sketches need to be at the same scaled to subtract hashes
+    let scaled = candidates
+        .iter()
+        .map(|(_, mh, _)| mh.scaled())
+        .fold(orig_query.scaled(), u64::max);
+    if scaled != orig_query.scaled() {
+        info!("downsampling query and matches to scaled={}", scaled);
+    }
+    orig_query = downsample_to_scaled(orig_query, scaled)?;
+
+    let track_abundance = orig_query.track_abundance() && !ignore_abundance;
+    if orig_query.track_abundance() && ignore_abundance {
+        orig_query.disable_abundance();
+    }
+    let sum_abunds: u64 = orig_query.to_vec_abunds().iter().map(|(_, a)| a).sum();
+    let mut candidates = candidates
+        .into_iter()
+        .map(|(sig, mh, db)| Ok((sig, downsample_to_scaled(mh, scaled)?, db)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let orig_size = orig_query.size() as f64;
+    let mut query_mh = orig_query.clone();
+    let mut results = Vec::new();
+
+    while !candidates.is_empty() {
+        let mut best = None;
+        for (pos, (_, mh, _)) in candidates.iter().enumerate() {
+            let common = query_mh.count_common(mh, true)?;
+            match best {
+                Some((_, best_common)) if best_common >= common => (),
+                _ => best = Some((pos, common)),
+            }
+        }
+
+        let (pos, unique_common) = best.unwrap();
+        if unique_common == 0 || unique_common * scaled < threshold_bp {
+            break;
+        }
+
+        let (match_sig, match_mh, db) = candidates.remove(pos);
+        let common = orig_query.count_common(&match_mh, true)?;
+        let f_match = common as f64 / match_mh.size() as f64;
+
+        // abundances for the hashes this match removes from the query
+        let match_hashes: HashSet<u64> = match_mh.iter_mins().cloned().collect();
+        let mut unique_abunds: Vec<u64> = query_mh
+            .to_vec_abunds()
+            .into_iter()
+            .filter_map(|(h, a)| {
+                if match_hashes.contains(&h) {
+                    Some(a)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let f_unique_weighted = unique_abunds.iter().sum::<u64>() as f64 / sum_abunds as f64;
+        let (average_abund, median_abund, std_abund) = if track_abundance {
+            let (average, median, std) = abundance_stats(&mut unique_abunds);
+            (Some(average), Some(median), Some(std))
+        } else {
+            (None, None, None)
+        };
+
+        query_mh.remove_from(&match_mh)?;
+
+        results.push(GatherResult {
+            intersect_bp: common * scaled,
+            f_orig_query: common as f64 / orig_size,
+            f_match,
+            f_unique_to_query: unique_common as f64 / orig_size,
+            f_unique_weighted,
+            average_abund,
+            median_abund,
+            std_abund,
+            ani: ani_from_containment(f_match, ksize),
+            match_sig,
+            db,
+            unique_intersect_bp: unique_common * scaled,
+            gather_result_rank: results.len(),
+            remaining_bp: query_mh.size() as u64 * scaled,
+        });
+    }
+
+    Ok(results)
+}
+
+/// Mean, median and (population) standard deviation of abundances.
+fn abundance_stats(abunds: &mut [u64]) -> (f64, f64, f64) {
+    if abunds.is_empty() {
+        return (0., 0., 0.);
+    }
+
+    abunds.sort_unstable();
+    let n = abunds.len();
+    let mean = abunds.iter().sum::<u64>() as f64 / n as f64;
+    let median = if n % 2 == 1 {
+        abunds[n / 2] as f64
+    } else {
+        (abunds[n / 2 - 1] + abunds[n / 2]) as f64 / 2.
+    };
+    let variance = abunds
+        .iter()
+        .map(|a| (*a as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n as f64;
+
+    (mean, median, variance.sqrt())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    Csv,
+    Json,
+    JsonLines,
+}
+
+impl OutputFormat {
+    /// Format from `--format` if given, otherwise from the output extension.
+    fn select(output: &str, format: Option<&str>) -> Result<OutputFormat> {
+        match format {
+            Some("csv") => Ok(OutputFormat::Csv),
+            Some("json") => Ok(OutputFormat::Json),
+            Some("jsonl") => Ok(OutputFormat::JsonLines),
+            Some(other) => Err(eyre!("unknown output format {}", other)),
+            None => {
+                if output.ends_with(".json") {
+                    Ok(OutputFormat::Json)
+                } else if output.ends_with(".jsonl") || output.ends_with(".ndjson") {
+                    Ok(OutputFormat::JsonLines)
+                } else {
+                    Ok(OutputFormat::Csv)
+                }
+            }
+        }
+    }
+}
+
+/// Writer for `output`, with `-` meaning stdout.
+fn output_writer(output: &str) -> Result<Box<dyn Write>> {
+    if output == "-" {
+        Ok(Box::new(io::stdout()))
+    } else {
+        Ok(Box::new(io::BufWriter::new(File::create(output)?)))
+    }
+}
+
+fn write_results<T: Serialize>(output: &str, results: &[T], format: OutputFormat) -> Result<()> {
+    let mut wrt = output_writer(output)?;
+
+    match format {
+        OutputFormat::Csv => {
+            let mut wrt = csv::Writer::from_writer(wrt);
+
+            for result in results {
+                wrt.serialize(result)?;
+            }
+            wrt.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut wrt, results)?;
+            writeln!(wrt)?;
+            wrt.flush()?;
+        }
+        OutputFormat::JsonLines => {
+            for result in results {
+                serde_json::to_writer(&mut wrt, result)?;
+                writeln!(wrt)?;
+            }
+            wrt.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Paths listed in a file, one per line, with `-` meaning stdin.
+fn read_path_list(list: &str) -> Result<Vec<String>> {
+    let contents = if list == "-" {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        contents
+    } else {
+        std::fs::read_to_string(list)?
+    };
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Load every query signature for the `multi*` commands,
+/// from the command line and from `--query-from-file`, selecting
+/// sketches the same way `search` does.
+fn load_queries(
+    cmd: &ArgMatches,
+    available: &dyn Fn() -> Result<Vec<(usize, HashFunctions)>>,
+) -> Result<Vec<Query<Signature>>> {
+    let mut paths: Vec<String> = cmd
+        .values_of("query")
+        .map(|vals| vals.map(String::from).collect())
+        .unwrap_or_default();
+
+    if let Some(list) = cmd.value_of("query-from-file") {
+        paths.extend(read_path_list(list)?);
+    }
+
+    if paths.is_empty() {
+        return Err(eyre!("No query signatures provided"));
+    }
+
+    let mut files = Vec::new();
+    for path in &paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            traverse_signatures(path, &mut files)?;
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    let ksize = match cmd.value_of("ksize") {
+        Some(k) => Some(k.parse()?),
+        None => None,
+    };
+    let moltype = moltype_from_args(cmd).to_string();
+
+    let mut queries = Vec::new();
+    for file in files {
+        queries.extend(load_query_signatures(
+            &file.to_string_lossy(),
+            ksize,
+            Some(&moltype),
+            available,
+        )?);
+    }
+
+    if queries.is_empty() {
+        return Err(eyre!("No query signatures loaded"));
+    }
+    check_query_params(&queries)?;
+
+    Ok(queries)
+}
+
+/// Check that all `queries` can be searched against the same databases.
+fn check_query_params(queries: &[Query<Signature>]) -> Result<()> {
+    for query in queries {
+        info!(
+            "loaded query: {}... (k={}, {})",
+            query.name(),
+            query.ksize(),
+            query.moltype()
+        );
+        if query.ksize() != queries[0].ksize() || query.moltype() != queries[0].moltype() {
+            return Err(eyre!(
+                "all queries must use the same ksize and molecule type: {} is (k={}, {}), {} is (k={}, {})",
+                queries[0].name(),
+                queries[0].ksize(),
+                queries[0].moltype(),
+                query.name(),
+                query.ksize(),
+                query.moltype()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check that every query can search every database, so nothing
+/// is written when one of them would fail halfway through.
+fn check_queries_compatible(
+    queries: &[Query<Signature>],
     databases: &[Database],
-    threshold: f64,
     containment: bool,
-    best_only: bool,
-    _ignore_abundance: bool,
-) -> Result<Vec<Results>> {
-    let mut results = Vec::default();
+) -> Result<()> {
+    for query in queries {
+        let query_mh = minhash_from_sig(&query.data)?;
+        for db in databases {
+            check_compatible(db, &query_mh, containment).map_err(|e| {
+                eyre!(
+                    "query {} can't be searched against {}: {}",
+                    query.name(),
+                    db.path,
+                    e
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
 
-    let search_fn = if best_only {
-        search_minhashes_find_best()
-    } else if containment {
-        search_minhashes_containment
+/// Output file name for a query in the `multi*` commands: the basename of the
+/// query name, or its md5sum if the name is empty or already used.
+fn query_output_name(query: &Signature, used: &mut HashSet<String>) -> String {
+    let name = query.name();
+    let name = Path::new(&name)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+        .to_string();
+
+    if !name.is_empty() && used.insert(name.clone()) {
+        name
     } else {
-        search_minhashes
-    };
-    let query_leaf = query.into();
-
-    // TODO: set up scaled for DB and query
-
-    for db in databases {
-        let matches = db.find(search_fn, &query_leaf, threshold).unwrap();
-        for dataset in matches.into_iter() {
-            let similarity = query_leaf.similarity(dataset);
-
-            // should always be true, but... better safe than sorry.
-            if similarity >= threshold {
-                results.push(Results {
-                    similarity,
-                    match_sig: dataset.clone(),
-                    db: db.path.clone(),
-                })
-            }
-        }
+        let md5 = query.md5sum();
+        used.insert(md5.clone());
+        md5
     }
+}
 
-    results.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
-    Ok(results)
+fn format_bp(bp: u64) -> String {
+    let bp = bp as f64;
+    if bp < 500. {
+        format!("{:.0} bp", bp)
+    } else if bp <= 500e3 {
+        format!("{:.1} kbp", bp / 1e3)
+    } else if bp < 500e6 {
+        format!("{:.1} Mbp", bp / 1e6)
+    } else {
+        format!("{:.1} Gbp", bp / 1e9)
+    }
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     //better_panic::install();
 
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
@@ -367,10 +2059,26 @@ fn main() -> Result<()> {
         }
         Some("index") => {
             let cmd = m.subcommand_matches("index").unwrap();
-            let inputs = cmd
+            let mut inputs: Vec<String> = cmd
                 .values_of("inputs")
-                .map(|vals| vals.collect::<Vec<_>>())
-                .expect("Missing inputs");
+                .map(|vals| vals.map(String::from).collect())
+                .unwrap_or_default();
+            if let Some(list) = cmd.value_of("from-file") {
+                inputs.extend(read_path_list(list)?);
+            }
+
+            let mut sig_files = Vec::new();
+            for input in &inputs {
+                let path = Path::new(input);
+                if cmd.is_present("traverse-directory") && path.is_dir() {
+                    traverse_signatures(path, &mut sig_files)?;
+                } else {
+                    sig_files.push(path.to_path_buf());
+                }
+            }
+            if sig_files.is_empty() {
+                return Err(eyre!("No signatures to index"));
+            }
 
             let output: &str = cmd.value_of("output").expect("Missing output");
             let (output, base) = if output.ends_with(".sbt.json") {
@@ -379,9 +2087,78 @@ fn main() -> Result<()> {
                 (output.to_owned() + ".sbt.json", output)
             };
 
-            let storage: Rc<dyn Storage> = Rc::new(FSStorage::new(".", &format!(".sbt.{}", base)));
+            let mut ksize = match cmd.value_of("ksize") {
+                Some(k) => Some(k.parse()?),
+                None => None,
+            };
+            let mut moltype = moltype_from_args(cmd);
+
+            let existing = if cmd.is_present("append") && Path::new(&output).exists() {
+                match Database::from_path(Path::new(&output), None, None)? {
+                    Some((db, _)) if !db.is_linear() => Some(db),
+                    _ => return Err(eyre!("can't append to {}, it isn't an SBT", output)),
+                }
+            } else {
+                None
+            };
+            if let Some(db) = &existing {
+                let (index_ksize, index_moltype) = match db.params {
+                    Some(params) => params,
+                    None => match db.first_signature()? {
+                        Some(first) => (
+                            first.sketches()[0].ksize(),
+                            first.sketches()[0].hash_function(),
+                        ),
+                        None => return Err(eyre!("can't append to {}, it's empty", output)),
+                    },
+                };
+                let moltype_selected = ["protein", "dayhoff", "hp", "dna"]
+                    .iter()
+                    .any(|m| cmd.is_present(m));
+                if ksize.unwrap_or(index_ksize) != index_ksize
+                    || (moltype_selected && moltype != index_moltype)
+                {
+                    return Err(eyre!(
+                        "{} uses {} sketches, can't append {} sketches to it",
+                        output,
+                        describe_sketch(index_ksize, index_moltype),
+                        describe_selection(ksize, moltype)
+                    ));
+                }
+                ksize = Some(index_ksize);
+                moltype = index_moltype;
+            }
 
-            index(inputs, storage, &output)?;
+            // storage is next to the index, same as sourmash and `MHBT::save_file`
+            let location = match Path::new(&output).parent() {
+                Some(parent) if parent != Path::new("") => parent.to_string_lossy().into_owned(),
+                _ => ".".into(),
+            };
+            let name = Path::new(base)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            // new leaves and nodes go next to the existing ones when appending
+            let subdir = match &existing {
+                Some(_) => read_description(Path::new(&output))?["storage"]["args"]["path"]
+                    .as_str()
+                    .map(String::from),
+                None => None,
+            }
+            .unwrap_or_else(|| format!(".sbt.{}", name));
+            let storage: Rc<dyn Storage> = Rc::new(FSStorage::new(&location, &subdir));
+
+            let processes: usize = cmd.value_of("processes").unwrap().parse()?;
+
+            index(
+                &sig_files,
+                existing.as_ref(),
+                storage,
+                &output,
+                ksize,
+                moltype,
+                processes,
+            )?;
         }
         Some("compute") => {
             let args = m.subcommand_matches("compute").unwrap();
@@ -470,15 +2247,9 @@ fn main() -> Result<()> {
             }
             params.set_merge(match args.value_of("name") {
                 Some(v) => Some(v.into()),
-                None => match args.value_of("merge") {
-                    Some(v) => Some(v.into()),
-                    None => None,
-                },
-            });
-            params.set_output(match args.value_of("output") {
-                Some(v) => Some(v.into()),
-                None => None,
+                None => args.value_of("merge").map(|v| v.into()),
             });
+            params.set_output(args.value_of("output").map(|v| v.into()));
 
             params.set_track_abundance(args.is_present("track-abundance"));
             if params.track_abundance() {
@@ -515,10 +2286,7 @@ fn main() -> Result<()> {
                 .parse()
                 .expect("Must be an integer");
 
-            params.output = match args.value_of("output") {
-                Some(v) => Some(v.into()),
-                None => None,
-            };
+            params.output = args.value_of("output").map(|v| v.into());
 
             params.ignore_abundance = args.is_present("ignore-abundance");
             if params.ignore_abundance {
@@ -530,7 +2298,54 @@ fn main() -> Result<()> {
                 info!("Use modhash instead of minhash");
             }
 
-            //compare(&signatures, &params);
+            params.protein = args.is_present("protein");
+            params.dayhoff = args.is_present("dayhoff");
+            params.hp = args.is_present("hp");
+            params.dna = !(params.protein || params.dayhoff || params.hp);
+
+            params.containment = args.is_present("containment");
+            params.max_containment = args.is_present("max-containment");
+            params.estimate_ani = args.is_present("estimate-ani");
+
+            params.csv = args.value_of("csv").map(|v| v.into());
+            params.traverse_directory = args.is_present("traverse-directory");
+            params.processes = match args.value_of("processes") {
+                Some(v) => v.parse()?,
+                None => 1,
+            };
+
+            let siglist = load_signatures(
+                &signatures,
+                Some(params.ksize as usize),
+                params.moltype(),
+                params.traverse_directory,
+            )?;
+
+            if siglist.len() < 2 {
+                return Err(eyre!(
+                    "must have at least two signatures to compare (found {})",
+                    siglist.len()
+                ));
+            }
+
+            let matrix = compare(&siglist, &params)?;
+
+            for (i, (sig, row)) in siglist.iter().zip(&matrix).enumerate() {
+                let mut name = sig.name();
+                if name.len() > 18 {
+                    name.truncate(15);
+                    name.push_str("...");
+                }
+                let row: Vec<String> = row.iter().map(|v| format!("{:.3}", v)).collect();
+                println!("{}-{}\t[{}]", i, name, row.join(" "));
+            }
+
+            let min_similarity = matrix
+                .iter()
+                .flatten()
+                .cloned()
+                .fold(f64::INFINITY, f64::min);
+            println!("min similarity in matrix: {:.3}", min_similarity);
         }
         Some("scaffold") => {
             let cmd = m.subcommand_matches("scaffold").unwrap();
@@ -550,6 +2365,219 @@ fn main() -> Result<()> {
                 log::set_max_level(LevelFilter::Warn);
             }
 
+            let scaled: u64 = cmd
+                .value_of("scaled")
+                .unwrap()
+                .parse()
+                .expect("--scaled value must be integer value");
+            let scaled = if scaled > 0 { Some(scaled) } else { None };
+
+            let db_paths = cmd
+                .values_of("databases")
+                .map(|vals| vals.collect::<Vec<_>>())
+                .unwrap();
+            let traverse_directory = cmd.is_present("traverse-directory");
+
+            let query_files: Vec<&str> = cmd.values_of("query").unwrap().collect();
+
+            let ksize = if cmd.is_present("ksize") {
+                Some(cmd.value_of("ksize").unwrap().parse().unwrap())
+            } else {
+                None
+            };
+            let moltype = moltype_from_args(cmd).to_string();
+            let containment = cmd.is_present("containment");
+            let best_only = cmd.is_present("best-only");
+            let threshold = cmd.value_of("threshold").unwrap().parse().unwrap();
+            let ignore_abundance = cmd.is_present("ignore-abundance");
+            let num_results: usize = cmd.value_of("num-results").unwrap().parse().unwrap();
+            let processes: usize = cmd.value_of("processes").unwrap().parse()?;
+
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(processes)
+                .build()?;
+
+            let params = SearchParams {
+                threshold,
+                containment,
+                best_only,
+                ignore_abundance,
+                scaled,
+            };
+
+            let (query_names, all_query_results) = pool.install(|| -> Result<_> {
+                // indices can't be shared between threads, so with more than one
+                // process each database file is loaded by the task searching it,
+                // or sent to it if the files were needed to select the query sketches
+                let mut paths = Vec::new();
+                let databases = if processes > 1 {
+                    paths = database_paths(&db_paths, traverse_directory)?;
+                    None
+                } else {
+                    Some(load_databases(&db_paths, traverse_directory)?)
+                };
+                let preloaded = RefCell::new(None);
+                let available = || match &databases {
+                    Some(databases) => database_params(databases),
+                    None => {
+                        let mut preloaded = preloaded.borrow_mut();
+                        if preloaded.is_none() {
+                            *preloaded = Some(preload_database_files(&paths)?);
+                        }
+                        Ok(preloaded
+                            .iter()
+                            .flatten()
+                            .flat_map(|(_, params)| params.iter().copied())
+                            .collect())
+                    }
+                };
+
+                let mut queries = Vec::new();
+                for query_file in &query_files {
+                    queries.extend(load_query_signatures(
+                        query_file,
+                        ksize,
+                        Some(&moltype),
+                        &available,
+                    )?);
+                }
+                check_query_params(&queries)?;
+
+                let query_names: Vec<String> = queries.iter().map(|q| q.name()).collect();
+                let databases = match databases {
+                    Some(databases) => {
+                        let databases = select_databases(databases, &queries, containment)?;
+                        let compatible: Vec<&[bool]> = databases
+                            .iter()
+                            .map(|db| db.compatible.as_slice())
+                            .collect();
+                        check_selected(&query_minhashes(&queries)?, &compatible)?;
+                        databases
+                    }
+                    None => {
+                        let preloaded = preloaded
+                            .into_inner()
+                            .map(|preloaded| preloaded.into_iter().map(|(file, _)| file).collect());
+                        let results = search_database_files(&paths, preloaded, &queries, &params)?;
+                        return Ok((query_names, results));
+                    }
+                };
+
+                if databases.is_empty() {
+                    return Err(eyre!("Nothing found to search!"));
+                }
+
+                let results = queries
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, query)| {
+                        let compatible: Vec<&Database> = databases
+                            .iter()
+                            .filter(|selected| selected.compatible[i])
+                            .map(|selected| &selected.db)
+                            .collect();
+                        search_databases(
+                            query,
+                            &compatible,
+                            &AtomicU64::new(threshold.to_bits()),
+                            &params,
+                        )
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok((query_names, results))
+            })?;
+
+            // with `-o -` results go to stdout, so the report goes to stderr
+            let mut report: Box<dyn Write> = if cmd.value_of("output") == Some("-") {
+                Box::new(io::stderr())
+            } else {
+                Box::new(io::stdout())
+            };
+
+            let n_queries = query_names.len();
+            let mut all_results = Vec::new();
+            let mut missing_best = false;
+            for (name, mut results) in query_names.iter().zip(all_query_results) {
+                if n_queries > 1 {
+                    writeln!(report, "query {}:", name)?;
+                }
+
+                let n_matches = if num_results == 0 || results.len() <= num_results {
+                    writeln!(report, "{} matches:", results.len())?;
+                    results.len()
+                } else {
+                    writeln!(
+                        report,
+                        "{} matches; showing first {}:",
+                        results.len(),
+                        num_results
+                    )?;
+                    num_results
+                };
+
+                writeln!(report, "similarity   match")?;
+                writeln!(report, "----------   -----")?;
+                for sr in &results[..n_matches] {
+                    writeln!(
+                        report,
+                        "{:>5.1}%       {:60}",
+                        sr.similarity * 100.,
+                        sr.match_sig.name()
+                    )?;
+                }
+
+                if best_only && results.is_empty() {
+                    missing_best = true;
+                }
+
+                results.truncate(n_matches);
+                all_results.extend(results);
+            }
+
+            if best_only {
+                info!("** reporting only one match because --best-only was set")
+            }
+
+            if let Some(output) = cmd.value_of("output") {
+                let format = OutputFormat::select(output, cmd.value_of("format"))?;
+                write_results(output, &all_results, format)?;
+            };
+
+            if let Some(outname) = cmd.value_of("save-matches") {
+                let mut writer = niffler::to_path(
+                    outname,
+                    niffler::compression::Format::No,
+                    niffler::compression::Level::One,
+                )?;
+
+                info!("saving all matched signatures to \"{}\"", outname);
+
+                let sigs: Vec<Signature> = all_results.into_iter().map(|sr| sr.match_sig).collect();
+                serde_json::to_writer(&mut writer, &sigs)?;
+            }
+
+            if missing_best {
+                error!(
+                    "no match found above threshold {} with --best-only",
+                    threshold
+                );
+                return Ok(ExitCode::from(2));
+            }
+        }
+        Some("prefetch") => {
+            let cmd = m.subcommand_matches("prefetch").unwrap();
+
+            if cmd.is_present("quiet") {
+                log::set_max_level(LevelFilter::Warn);
+            }
+
+            let db_paths = cmd
+                .values_of("databases")
+                .map(|vals| vals.collect::<Vec<_>>())
+                .unwrap();
+            let traverse_directory = cmd.is_present("traverse-directory");
+
+            let databases = load_databases(&db_paths, traverse_directory)?;
             let query = load_query_signature(
                 cmd.value_of("query").unwrap(),
                 if cmd.is_present("ksize") {
@@ -557,12 +2585,8 @@ fn main() -> Result<()> {
                 } else {
                     None
                 },
-                Some("dna"), // TODO: select moltype,
-                if cmd.is_present("scaled") {
-                    Some(cmd.value_of("scaled").unwrap().parse().unwrap())
-                } else {
-                    None
-                },
+                Some(&moltype_from_args(cmd).to_string()),
+                &|| database_params(&databases),
             )?;
 
             info!(
@@ -572,70 +2596,160 @@ fn main() -> Result<()> {
                 query.moltype()
             );
 
-            // TODO: check args.scaled and downsample
+            let databases: Vec<Database> =
+                select_databases(databases, std::slice::from_ref(&query), true)?
+                    .into_iter()
+                    .map(|selected| selected.db)
+                    .collect();
 
-            let containment = cmd.is_present("containment");
+            if databases.is_empty() {
+                return Err(eyre!("Nothing found to search!"));
+            }
+
+            let mut csv_writer = match cmd.value_of("output") {
+                Some(output) => Some(csv::Writer::from_path(output)?),
+                None => None,
+            };
+
+            let mut sig_writer = match cmd.value_of("save-matches") {
+                Some(outname) => {
+                    info!("saving all matched signatures to \"{}\"", outname);
+                    let mut writer = niffler::to_path(
+                        outname,
+                        niffler::compression::Format::No,
+                        niffler::compression::Level::One,
+                    )?;
+                    writer.write_all(b"[")?;
+                    Some(writer)
+                }
+                None => None,
+            };
+
+            let threshold_bp = cmd.value_of("threshold-bp").unwrap().parse()?;
+            let query: Signature = query.into();
+            let mut n_saved = 0;
+            let n_matches = prefetch_databases(&query, &databases, threshold_bp, |pr| {
+                info!(
+                    "found match: {} ({} overlap)",
+                    pr.match_sig.name(),
+                    format_bp(pr.intersect_bp)
+                );
+
+                if let Some(wrt) = csv_writer.as_mut() {
+                    wrt.serialize(&pr)?;
+                    wrt.flush()?;
+                }
+
+                if let Some(writer) = sig_writer.as_mut() {
+                    if n_saved > 0 {
+                        writer.write_all(b",")?;
+                    }
+                    serde_json::to_writer(&mut *writer, &pr.match_sig)?;
+                    n_saved += 1;
+                }
+                Ok(())
+            })?;
+
+            if let Some(mut writer) = sig_writer {
+                writer.write_all(b"]")?;
+            }
+
+            println!("total of {} matching signatures.", n_matches);
+        }
+        Some("gather") => {
+            let cmd = m.subcommand_matches("gather").unwrap();
+
+            if cmd.is_present("quiet") {
+                log::set_max_level(LevelFilter::Warn);
+            }
+
+            let db_paths = cmd
+                .values_of("databases")
+                .map(|vals| vals.collect::<Vec<_>>())
+                .unwrap();
             let traverse_directory = cmd.is_present("traverse-directory");
-            let databases = load_sbts_and_sigs(
-                &cmd.values_of("databases")
-                    .map(|vals| vals.collect::<Vec<_>>())
-                    .unwrap(),
-                &query,
-                containment,
-                traverse_directory,
+
+            let databases = load_databases(&db_paths, traverse_directory)?;
+            let query = load_query_signature(
+                cmd.value_of("query").unwrap(),
+                if cmd.is_present("ksize") {
+                    Some(cmd.value_of("ksize").unwrap().parse().unwrap())
+                } else {
+                    None
+                },
+                Some(&moltype_from_args(cmd).to_string()),
+                &|| database_params(&databases),
             )?;
 
+            info!(
+                "loaded query: {}... (k={}, {})",
+                query.name(),
+                query.ksize(),
+                query.moltype()
+            );
+
+            let databases: Vec<Database> =
+                select_databases(databases, std::slice::from_ref(&query), true)?
+                    .into_iter()
+                    .map(|selected| selected.db)
+                    .collect();
+
             if databases.is_empty() {
                 return Err(eyre!("Nothing found to search!"));
             }
 
-            let best_only = cmd.is_present("best-only");
-            let threshold = cmd.value_of("threshold").unwrap().parse().unwrap();
+            let threshold_bp = cmd.value_of("threshold-bp").unwrap().parse()?;
             let ignore_abundance = cmd.is_present("ignore-abundance");
-            let results = search_databases(
-                query,
-                &databases,
-                threshold,
-                containment,
-                best_only,
-                ignore_abundance,
-            )?;
+            let results = gather_databases(query, &databases, threshold_bp, ignore_abundance)?;
 
-            let num_results = if best_only {
-                1
-            } else {
-                cmd.value_of("num-results").unwrap().parse().unwrap()
-            };
+            let track_abundance = results.iter().any(|gr| gr.average_abund.is_some());
 
-            let n_matches = if num_results == 0 || results.len() <= num_results {
-                println!("{} matches:", results.len());
-                results.len()
+            println!();
+            if track_abundance {
+                println!("overlap     p_query p_match avg_abund");
+                println!("---------   ------- ------- ---------");
             } else {
-                println!("{} matches; showing first {}:", results.len(), num_results);
-                num_results
-            };
+                println!("overlap     p_query p_match");
+                println!("---------   ------- -------");
+            }
+            for gr in &results {
+                if let Some(average_abund) = gr.average_abund {
+                    println!(
+                        "{:9}   {:>6.1}% {:>6.1}% {:>9.1}    {}",
+                        format_bp(gr.intersect_bp),
+                        gr.f_orig_query * 100.,
+                        gr.f_match * 100.,
+                        average_abund,
+                        gr.match_sig.name()
+                    );
+                } else {
+                    println!(
+                        "{:9}   {:>6.1}% {:>6.1}%    {}",
+                        format_bp(gr.intersect_bp),
+                        gr.f_orig_query * 100.,
+                        gr.f_match * 100.,
+                        gr.match_sig.name()
+                    );
+                }
+            }
 
-            println!("similarity   match");
-            println!("----------   -----");
-            for sr in &results[..n_matches] {
+            let f_recovered: f64 = results.iter().map(|gr| gr.f_unique_to_query).sum();
+            println!();
+            println!("found {} matches total;", results.len());
+            println!(
+                "the recovered matches hit {:.1}% of the query",
+                f_recovered * 100.
+            );
+            if track_abundance {
+                let f_weighted: f64 = results.iter().map(|gr| gr.f_unique_weighted).sum();
                 println!(
-                    "{:>5.1}%       {:60}",
-                    sr.similarity * 100.,
-                    sr.match_sig.name()
+                    "the recovered matches hit {:.1}% of the query (abundance-weighted)",
+                    f_weighted * 100.
                 );
             }
 
-            if best_only {
-                info!("** reporting only one match because --best-only was set")
-            }
-
             if let Some(output) = cmd.value_of("output") {
-                let mut wrt = csv::Writer::from_path(output)?;
-
-                for sr in &results[..n_matches] {
-                    wrt.serialize(sr)?;
-                }
-                wrt.flush()?;
+                write_results(output, &results, OutputFormat::Csv)?;
             };
 
             if let Some(outname) = cmd.value_of("save-matches") {
@@ -647,13 +2761,123 @@ fn main() -> Result<()> {
 
                 info!("saving all matched signatures to \"{}\"", outname);
 
-                let sigs: Vec<Signature> = results.into_iter().map(|sr| sr.match_sig).collect();
+                let sigs: Vec<Signature> = results.into_iter().map(|gr| gr.match_sig).collect();
                 serde_json::to_writer(&mut writer, &sigs)?;
             }
         }
+        Some("multigather") => {
+            let cmd = m.subcommand_matches("multigather").unwrap();
+
+            if cmd.is_present("quiet") {
+                log::set_max_level(LevelFilter::Warn);
+            }
+
+            let db_paths = cmd
+                .values_of("databases")
+                .map(|vals| vals.collect::<Vec<_>>())
+                .unwrap();
+            let traverse_directory = cmd.is_present("traverse-directory");
+
+            let databases = load_databases(&db_paths, traverse_directory)?;
+            let queries = load_queries(cmd, &|| database_params(&databases))?;
+
+            let databases: Vec<Database> = select_databases(databases, &queries, true)?
+                .into_iter()
+                .map(|selected| selected.db)
+                .collect();
+
+            if databases.is_empty() {
+                return Err(eyre!("Nothing found to search!"));
+            }
+            check_queries_compatible(&queries, &databases, true)?;
+
+            let threshold_bp = cmd.value_of("threshold-bp").unwrap().parse()?;
+            let ignore_abundance = cmd.is_present("ignore-abundance");
+            let output_dir = Path::new(cmd.value_of("output-dir").unwrap());
+            std::fs::create_dir_all(output_dir)?;
+
+            let mut used_names = HashSet::new();
+            for query in queries {
+                let output = output_dir.join(format!(
+                    "{}.csv",
+                    query_output_name(&query.data, &mut used_names)
+                ));
+                let name = query.name();
+                let results = gather_databases(query, &databases, threshold_bp, ignore_abundance)?;
+
+                println!(
+                    "found {} matches for {}, saved to {}",
+                    results.len(),
+                    name,
+                    output.display()
+                );
+                write_results(output.to_str().unwrap(), &results, OutputFormat::Csv)?;
+            }
+        }
+        Some("multisearch") => {
+            let cmd = m.subcommand_matches("multisearch").unwrap();
+
+            if cmd.is_present("quiet") {
+                log::set_max_level(LevelFilter::Warn);
+            }
+
+            let db_paths = cmd
+                .values_of("databases")
+                .map(|vals| vals.collect::<Vec<_>>())
+                .unwrap();
+            let traverse_directory = cmd.is_present("traverse-directory");
+
+            let databases = load_databases(&db_paths, traverse_directory)?;
+            let queries = load_queries(cmd, &|| database_params(&databases))?;
+
+            let containment = cmd.is_present("containment");
+            let databases: Vec<Database> = select_databases(databases, &queries, containment)?
+                .into_iter()
+                .map(|selected| selected.db)
+                .collect();
+
+            if databases.is_empty() {
+                return Err(eyre!("Nothing found to search!"));
+            }
+            check_queries_compatible(&queries, &databases, containment)?;
+
+            let params = SearchParams {
+                threshold: cmd.value_of("threshold").unwrap().parse()?,
+                containment,
+                best_only: false,
+                ignore_abundance: cmd.is_present("ignore-abundance"),
+                scaled: None,
+            };
+            let databases: Vec<&Database> = databases.iter().collect();
+            let output_dir = Path::new(cmd.value_of("output-dir").unwrap());
+            std::fs::create_dir_all(output_dir)?;
+
+            let mut used_names = HashSet::new();
+            for query in queries {
+                let output = output_dir.join(format!(
+                    "{}.csv",
+                    query_output_name(&query.data, &mut used_names)
+                ));
+                let name = query.name();
+                let results = search_databases(
+                    query,
+                    &databases,
+                    &AtomicU64::new(params.threshold.to_bits()),
+                    &params,
+                )?;
+
+                println!(
+                    "found {} matches for {}, saved to {}",
+                    results.len(),
+                    name,
+                    output.display()
+                );
+                write_results(output.to_str().unwrap(), &results, OutputFormat::Csv)?;
+            }
+        }
         _ => {
             println!("{:?}", m);
         }
     }
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
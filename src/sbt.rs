@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use eyre::{eyre, Result};
+
+use sourmash::index::sbt::Node;
+use sourmash::index::storage::{FSStorage, ReadData, Storage};
+use sourmash::index::{Comparable, SigStore};
+use sourmash::signature::Signature;
+use sourmash::sketch::nodegraph::Nodegraph;
+
+/// A node of an SBT, as passed to the search function of `Sbt::find`.
+#[derive(Clone, Copy)]
+pub enum SbtNode<'a> {
+    /// An internal node, holding the hashes of every leaf below it.
+    Internal(&'a Node<Nodegraph>),
+    Leaf(&'a Signature),
+}
+
+impl<'a> SbtNode<'a> {
+    /// The node as taken by the search functions used with `Index::find`.
+    pub fn comparable(self) -> &'a dyn Comparable<Signature> {
+        match self {
+            SbtNode::Internal(node) => node,
+            SbtNode::Leaf(sig) => sig,
+        }
+    }
+}
+
+/// An SBT loaded from its description, like `MHBT::from_path`, but searched
+/// knowing which nodes are leaves: the containment of an internal node is
+/// relative to the query, and the containment of a leaf to the leaf.
+pub struct Sbt {
+    d: u64,
+    nodes: HashMap<u64, Node<Nodegraph>>,
+    leaves: HashMap<u64, SigStore<Signature>>,
+}
+
+impl Sbt {
+    /// Load the description at `path`. Nodes and leaves are read
+    /// from the storage next to it the first time they are used.
+    pub fn from_path(path: &Path) -> Result<Sbt> {
+        let description: serde_json::Value =
+            serde_json::from_reader(io::BufReader::new(File::open(path)?))?;
+
+        let d = description["d"]
+            .as_u64()
+            .ok_or_else(|| eyre!("{} is not an SBT description", path.display()))?;
+        let subdir = description["storage"]["args"]["path"]
+            .as_str()
+            .ok_or_else(|| eyre!("{} has no storage", path.display()))?;
+        let location = path
+            .parent()
+            .map(|parent| parent.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let storage: Rc<dyn Storage> = Rc::new(FSStorage::new(&location, subdir));
+
+        let mut nodes = HashMap::new();
+        let mut leaves = HashMap::new();
+        // v4 keeps leaves with the internal nodes, v5 in `leaves` and v6 in `signatures`
+        for section in &["nodes", "leaves", "signatures"] {
+            let entries = match description[section].as_object() {
+                Some(entries) => entries,
+                None => continue,
+            };
+
+            for (pos, entry) in entries {
+                let pos: u64 = pos.parse()?;
+                let filename = entry["filename"].as_str().unwrap_or_default();
+                let name = entry["name"].as_str().unwrap_or_default();
+
+                match &entry["metadata"] {
+                    serde_json::Value::Object(metadata) if *section == "nodes" => {
+                        let metadata = metadata
+                            .iter()
+                            .filter_map(|(key, value)| Some((key.clone(), value.as_u64()?)))
+                            .collect();
+                        let node = Node::builder()
+                            .filename(filename)
+                            .name(name)
+                            .metadata(metadata)
+                            .storage(Some(Rc::clone(&storage)))
+                            .build();
+                        nodes.insert(pos, node);
+                    }
+                    metadata => {
+                        let leaf = SigStore::builder()
+                            .filename(filename)
+                            .name(name)
+                            .metadata(metadata.as_str().unwrap_or_default())
+                            .storage(Some(Rc::clone(&storage)))
+                            .build();
+                        leaves.insert(pos, leaf);
+                    }
+                }
+            }
+        }
+
+        Ok(Sbt { d, nodes, leaves })
+    }
+
+    /// Search the tree depth-first, in the same order as `SBT::find`, skipping
+    /// everything below the internal nodes rejected by `search_fn`.
+    pub fn find<F>(
+        &self,
+        search_fn: F,
+        sig: &Signature,
+        threshold: f64,
+    ) -> std::result::Result<Vec<&Signature>, sourmash::Error>
+    where
+        F: Fn(SbtNode, &Signature, f64) -> bool,
+    {
+        let mut matches = Vec::new();
+        let mut queue = vec![0u64];
+
+        while let Some(pos) = queue.pop() {
+            if let Some(node) = self.nodes.get(&pos) {
+                if search_fn(SbtNode::Internal(node), sig, threshold) {
+                    queue.extend((1..=self.d).map(|child| self.d * pos + child));
+                }
+            } else if let Some(leaf) = self.leaves.get(&pos) {
+                let data = leaf.data()?;
+                if search_fn(SbtNode::Leaf(data), sig, threshold) {
+                    matches.push(data);
+                }
+            }
+        }
+
+        Ok(matches)
+    }
+
+    /// Leaves in the order of their positions in the tree.
+    pub fn signature_refs(&self) -> Vec<&Signature> {
+        let mut positions: Vec<&u64> = self.leaves.keys().collect();
+        positions.sort_unstable();
+        positions
+            .into_iter()
+            .map(|pos| self.leaves[pos].data().expect("Error reading data"))
+            .collect()
+    }
+
+    /// The leaf with the lowest position, without loading the others.
+    pub fn first_signature(&self) -> Result<Option<&Signature>> {
+        match self.leaves.keys().min() {
+            Some(pos) => Ok(Some(self.leaves[pos].data()?)),
+            None => Ok(None),
+        }
+    }
+}
@@ -1,8 +1,13 @@
+#![allow(clippy::needless_borrows_for_generic_args)]
+
+use std::collections::HashMap;
 use std::fs;
 use std::io::{Read, Write};
+use std::path::PathBuf;
 use std::process::Command;
 
 use assert_cmd::prelude::*;
+use predicates::prelude::*;
 use predicates::str::contains;
 use sourmash::signature::Signature;
 use tempfile::TempDir;
@@ -23,6 +28,168 @@ fn search() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn search_signatures() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("decoct")?;
+
+    cmd.arg("search")
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/SRR2060939_2.sig")
+        .assert()
+        .success()
+        .stdout(contains("SRR2060939_1.fastq.gz"))
+        .stdout(contains("SRR2060939_2.fastq.gz"));
+
+    Ok(())
+}
+
+#[test]
+fn search_traverse_directory() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    fs::copy("tests/data/SRR2060939_1.sig", tmp_dir.path().join("1.sig"))?;
+    fs::copy("tests/data/SRR2060939_2.sig", tmp_dir.path().join("2.sig"))?;
+    fs::write(tmp_dir.path().join("notes.txt"), "not a signature")?;
+    fs::write(tmp_dir.path().join("broken.sig"), "not a signature")?;
+
+    let nested = tmp_dir.path().join("nested");
+    fs::create_dir_all(nested.join(".sbt.v3"))?;
+    fs::copy("tests/data/v5.sbt.json", nested.join("v5.sbt.json"))?;
+    for entry in fs::read_dir("tests/data/.sbt.v3")? {
+        let entry = entry?;
+        fs::copy(entry.path(), nested.join(".sbt.v3").join(entry.file_name()))?;
+    }
+
+    for processes in &["1", "4"] {
+        let mut cmd = Command::cargo_bin("decoct")?;
+
+        cmd.arg("search")
+            .arg("--traverse-directory")
+            .args(["-p", processes])
+            .arg("tests/data/SRR2060939_1.sig")
+            .arg(tmp_dir.path())
+            .assert()
+            .success()
+            .stdout(contains("SRR2060939_1.fastq.gz"))
+            .stdout(contains("SRR2060939_2.fastq.gz"))
+            .stderr(contains("loaded SBT"))
+            .stderr(contains("loaded 3 files"))
+            .stderr(contains("skipped 1"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn search_protein() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+
+    let dna = fs::read_to_string("tests/data/SRR2060939_1.sig")?;
+    let protein = dna
+        .replace("\"molecule\": \"dna\"", "\"molecule\": \"protein\"")
+        .replace("SRR2060939_1.fastq.gz", "SRR2060939_1.protein");
+    fs::write(tmp_dir.path().join("protein.sig"), &protein)?;
+
+    let mut db = Signature::from_path("tests/data/SRR2060939_1.sig")?;
+    db.extend(Signature::from_path(tmp_dir.path().join("protein.sig"))?);
+    serde_json::to_writer(fs::File::create(tmp_dir.path().join("db.sig"))?, &db)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .arg("--protein")
+        .arg(tmp_dir.path().join("protein.sig"))
+        .arg(tmp_dir.path().join("db.sig"))
+        .assert()
+        .success()
+        .stdout(contains("1 matches"))
+        .stdout(contains("SRR2060939_1.protein"));
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .arg(tmp_dir.path().join("protein.sig"))
+        .arg(tmp_dir.path().join("db.sig"))
+        .assert()
+        .failure()
+        .stderr(contains("no query signature found"));
+
+    Ok(())
+}
+
+#[test]
+fn search_best_only() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .arg("--best-only")
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/SRR2060939_2.sig")
+        .arg("tests/data/v5.sbt.json")
+        .assert()
+        .success()
+        .stdout(contains("1 matches:"))
+        .stdout(contains("100.0%       SRR2060939_1.fastq.gz"))
+        .stdout(contains("SRR2060939_2.fastq.gz").not());
+
+    // the threshold is raised while traversing the SBT
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .arg("--best-only")
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/v5.sbt.json")
+        .assert()
+        .success()
+        .stdout(contains("1 matches:"))
+        .stdout(contains("100.0%       SRR2060939_1.fastq.gz"));
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--best-only", "--threshold", "0.9"])
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/SRR2060939_2.sig")
+        .assert()
+        .code(2)
+        .stdout(contains("0 matches:"))
+        .stderr(contains("no match found"));
+
+    Ok(())
+}
+
+#[test]
+fn search_processes() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+
+    let mut outputs = Vec::new();
+    for processes in &["1", "4"] {
+        let output = tmp_dir.path().join(format!("matches.{}.csv", processes));
+        let mut cmd = Command::cargo_bin("decoct")?;
+        cmd.arg("search")
+            .args(["-p", processes, "-n", "0"])
+            .arg("tests/data/SRR2060939_1.sig")
+            .arg("tests/data/SRR2060939_2.sig")
+            .arg("tests/data/v5.sbt.json")
+            .arg("tests/data/SRR2060939_1.sig")
+            .arg("-o")
+            .arg(&output)
+            .assert()
+            .success()
+            .stdout(contains("5 matches:"));
+        outputs.push(fs::read_to_string(&output)?);
+    }
+    assert_eq!(outputs[0], outputs[1]);
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["-p", "4", "--best-only"])
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/SRR2060939_2.sig")
+        .arg("tests/data/v5.sbt.json")
+        .assert()
+        .success()
+        .stdout(contains("1 matches:"))
+        .stdout(contains("100.0%       SRR2060939_1.fastq.gz"));
+
+    Ok(())
+}
+
 #[test]
 #[ignore]
 fn search_only_leaves() -> Result<(), Box<dyn std::error::Error>> {
@@ -421,3 +588,1353 @@ fn compute_name_from_first() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn compare() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("decoct")?;
+
+    cmd.arg("compare")
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/SRR2060939_2.sig")
+        .assert()
+        .success()
+        .stdout(contains("0-SRR2060939_1.fa...\t[1.000 0.356]"))
+        .stdout(contains("1-SRR2060939_2.fa...\t[0.356 1.000]"))
+        .stdout(contains("min similarity in matrix: 0.356"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn compare_output() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    fs::copy("tests/data/SRR2060939_1.sig", tmp_dir.path().join("1.sig"))?;
+    fs::copy("tests/data/SRR2060939_2.sig", tmp_dir.path().join("2.sig"))?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compare")
+        .args(["1.sig", "2.sig"])
+        .args(["-o", "cmp.npy"])
+        .args(["--csv", "cmp.csv"])
+        .args(["-p", "2"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    assert!(tmp_dir.path().join("cmp.npy").exists());
+
+    let labels = fs::read_to_string(tmp_dir.path().join("cmp.npy.labels.txt"))?;
+    assert_eq!(labels, "SRR2060939_1.fastq.gz\nSRR2060939_2.fastq.gz\n");
+
+    let csv = fs::read_to_string(tmp_dir.path().join("cmp.csv"))?;
+    assert_eq!(
+        csv,
+        "SRR2060939_1.fastq.gz,SRR2060939_2.fastq.gz\n1.0,0.356\n0.356,1.0\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn compare_containment() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    fs::copy(
+        "tests/data/ecoli.genes.fna",
+        tmp_dir.path().join("ecoli.fna"),
+    )?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .arg("ecoli.fna")
+        .args(["-k", "31", "--scaled", "1", "-o", "all.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .arg("ecoli.fna")
+        .args(["-k", "31", "--scaled", "1", "-o", "genes.sig"])
+        .arg("--singleton")
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compare")
+        .arg("--containment")
+        .args(["all.sig", "genes.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("0-ecoli.fna\t[1.000 0.729 0.271]"))
+        .stdout(contains("1-gi|556503834:33...\t[1.000 1.000 0.000]"))
+        .stdout(contains("2-gi|556503834:28...\t[1.000 0.000 1.000]"));
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compare")
+        .arg("--max-containment")
+        .args(["all.sig", "genes.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("0-ecoli.fna\t[1.000 1.000 1.000]"));
+
+    Ok(())
+}
+
+#[test]
+fn compare_containment_num() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("decoct")?;
+
+    cmd.arg("compare")
+        .arg("--containment")
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/SRR2060939_2.sig")
+        .assert()
+        .failure()
+        .stderr(contains("must use scaled signatures"));
+
+    Ok(())
+}
+
+#[test]
+fn compare_ani() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("decoct")?;
+
+    cmd.arg("compare")
+        .arg("--estimate-ani")
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/SRR2060939_2.sig")
+        .assert()
+        .success()
+        .stdout(contains("0-SRR2060939_1.fa...\t[1.000 0.979]"))
+        .stdout(contains("1-SRR2060939_2.fa...\t[0.979 1.000]"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn search_output_ani() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    let output = tmp_dir.path().join("matches.csv");
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/v5.sbt.json")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let mut rdr = csv::Reader::from_path(&output)?;
+    assert_eq!(
+        rdr.headers()?.iter().take(5).collect::<Vec<_>>(),
+        vec!["similarity", "name", "filename", "md5", "ani"]
+    );
+
+    let rows = rdr.records().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(rows.len(), 3);
+    assert_eq!(&rows[0][1], "SRR2060939_1.fastq.gz");
+    assert_eq!(&rows[0][4], "1.0");
+    assert_eq!(&rows[1][1], "SRR2060939_2.fastq.gz");
+    assert!(rows[1][4].starts_with("0.979"));
+
+    Ok(())
+}
+
+#[test]
+fn search_multiple_queries() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    let output = tmp_dir.path().join("matches.csv");
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .arg("tests/data/SRR2060939_1.sig,tests/data/SRR2060939_2.sig")
+        .arg("tests/data/v5.sbt.json")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success()
+        .stdout(contains("query SRR2060939_1.fastq.gz:"))
+        .stdout(contains("query SRR2060939_2.fastq.gz:"));
+
+    let mut rdr = csv::Reader::from_path(&output)?;
+    let rows = rdr
+        .deserialize::<HashMap<String, String>>()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(rows.len(), 5);
+    assert_eq!(rows[0]["query_name"], "SRR2060939_1.fastq.gz");
+    assert_eq!(rows[0]["query_md5"], "60f7e23c24a8d94791cc7a8680c493f9");
+    assert_eq!(rows[0]["name"], "SRR2060939_1.fastq.gz");
+    assert_eq!(rows[3]["query_name"], "SRR2060939_2.fastq.gz");
+    assert_eq!(rows[3]["name"], "SRR2060939_2.fastq.gz");
+
+    // a single file with several signatures
+    let mut sigs = Signature::from_path("tests/data/SRR2060939_1.sig")?;
+    sigs.extend(Signature::from_path("tests/data/SRR2060939_2.sig")?);
+    let queries = tmp_dir.path().join("queries.sig");
+    serde_json::to_writer(fs::File::create(&queries)?, &sigs)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .arg(&queries)
+        .arg("tests/data/v5.sbt.json")
+        .assert()
+        .success()
+        .stdout(contains("query SRR2060939_1.fastq.gz:"))
+        .stdout(contains("query SRR2060939_2.fastq.gz:"));
+
+    Ok(())
+}
+
+#[test]
+fn search_output_json() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    let output = tmp_dir.path().join("matches.json");
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/v5.sbt.json")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let results: Vec<HashMap<String, serde_json::Value>> =
+        serde_json::from_reader(fs::File::open(&output)?)?;
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["name"], "SRR2060939_1.fastq.gz");
+    assert_eq!(results[0]["similarity"], 1.0);
+
+    // --format takes precedence over the extension
+    let output = tmp_dir.path().join("matches.txt");
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/v5.sbt.json")
+        .args(["--format", "jsonl", "-o"])
+        .arg(&output)
+        .assert()
+        .success();
+
+    let lines = fs::read_to_string(&output)?;
+    let results = lines
+        .lines()
+        .map(serde_json::from_str::<HashMap<String, serde_json::Value>>)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[1]["name"], "SRR2060939_2.fastq.gz");
+
+    Ok(())
+}
+
+#[test]
+fn search_output_stdout() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("decoct")?;
+    let output = cmd
+        .arg("search")
+        .arg("tests/data/SRR2060939_1.sig")
+        .arg("tests/data/v5.sbt.json")
+        .args(["-o", "-"])
+        .output()?;
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("3 matches"));
+    assert!(stderr.contains("similarity   match"));
+
+    let mut rdr = csv::Reader::from_reader(&output.stdout[..]);
+    let rows = rdr
+        .deserialize::<HashMap<String, String>>()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0]["name"], "SRR2060939_1.fastq.gz");
+
+    Ok(())
+}
+
+/// Split the two E. coli genes into separate files, and compute scaled
+/// signatures for each gene (`gene{1,2}.fa.sig`) and for both (`all.sig`).
+#[cfg(unix)]
+fn prepare_scaled_sigs(tmp_dir: &TempDir) -> Result<(), Box<dyn std::error::Error>> {
+    let genes = fs::read_to_string("tests/data/ecoli.genes.fna")?;
+    for (i, record) in genes.split('>').skip(1).enumerate() {
+        fs::write(
+            tmp_dir.path().join(format!("gene{}.fa", i + 1)),
+            format!(">{}", record),
+        )?;
+    }
+    fs::write(tmp_dir.path().join("ecoli.fna"), genes)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "31", "--scaled", "1"])
+        .args(["gene1.fa", "gene2.fa"])
+        .current_dir(tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "31", "--scaled", "1", "-o", "all.sig"])
+        .arg("ecoli.fna")
+        .current_dir(tmp_dir)
+        .assert()
+        .success();
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn search_downsample() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "31", "--scaled", "10", "-o", "all10.sig"])
+        .arg("ecoli.fna")
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-o", "genes.sbt.json", "gene1.fa.sig", "gene2.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    // query at scaled=1, database at scaled=10
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--containment", "gene1.fa.sig", "all10.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("100.0%"))
+        .stdout(contains("ecoli.fna"))
+        .stderr(contains("downsampling query to scaled=10"));
+
+    // query at scaled=10, SBT at scaled=1
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--containment", "all10.sig", "genes.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("gene1.fa"))
+        .stdout(contains("gene2.fa"))
+        .stderr(contains("downsampling its signatures to scaled=10"));
+
+    // explicit --scaled
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--scaled", "10", "--containment", "gene1.fa.sig", "all.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("100.0%"))
+        .stderr(contains("downsampling query from scaled=1 to scaled=10"));
+
+    // no hashes left in the query after downsampling
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args([
+            "--scaled",
+            "1000000",
+            "--containment",
+            "gene2.fa.sig",
+            "all.sig",
+        ])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("0 matches"))
+        .stderr(contains("query has no hashes left at scaled=1000000"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn search_incompatible() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "21", "--scaled", "1", "-o", "gene1.k21.sig"])
+        .arg("gene1.fa")
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-o", "k21.sbt.json", "gene1.k21.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    for processes in &["1", "4"] {
+        let mut cmd = Command::cargo_bin("decoct")?;
+        cmd.arg("search")
+            .args(["-p", processes, "gene1.fa.sig", "k21.sbt.json"])
+            .current_dir(&tmp_dir)
+            .assert()
+            .failure()
+            .stderr(contains(
+                "ksize mismatch: query uses k=31, database uses k=21",
+            ))
+            .stderr(contains("no databases compatible with the query"));
+    }
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["gene1.fa.sig", "k21.sbt.json", "all.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("ecoli.fna"))
+        .stderr(contains("skipping k21.sbt.json"));
+
+    // databases are selected for each query
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "31", "--scaled", "0", "--num-hashes", "500"])
+        .args(["-o", "gene1.num.sig", "gene1.fa"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    for processes in &["1", "4"] {
+        let mut cmd = Command::cargo_bin("decoct")?;
+        cmd.arg("search")
+            .args(["-p", processes, "gene1.fa.sig,gene1.num.sig"])
+            .args(["all.sig", "gene1.num.sig"])
+            .current_dir(&tmp_dir)
+            .assert()
+            .success()
+            .stdout(contains("ecoli.fna"))
+            .stdout(contains("100.0%"))
+            .stderr(contains("skipping all.sig for query gene1.fa"))
+            .stderr(contains("skipping gene1.num.sig for query gene1.fa"));
+    }
+
+    // num signatures can't be used for containment
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .arg("--containment")
+        .arg(fs::canonicalize("tests/data/SRR2060939_1.sig")?)
+        .arg(fs::canonicalize("tests/data/v5.sbt.json")?)
+        .current_dir(&tmp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("containment requires scaled signatures"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn search_select_query_sketch() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "21,31", "--scaled", "1", "-o", "gene1.multi.sig"])
+        .arg("gene1.fa")
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "21,31", "--scaled", "1", "-o", "all.multi.sig"])
+        .arg("ecoli.fna")
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    // only k=31 is available in the database
+    for processes in &["1", "4"] {
+        let mut cmd = Command::cargo_bin("decoct")?;
+        cmd.arg("search")
+            .args(["-p", processes])
+            .args(["--containment", "gene1.multi.sig", "all.sig"])
+            .current_dir(&tmp_dir)
+            .assert()
+            .success()
+            .stdout(contains("100.0%"))
+            .stderr(contains("selected query sketch (k=31, DNA)"))
+            .stderr(contains("loaded 1 signatures from all.sig").count(1));
+    }
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["gene1.multi.sig", "all.multi.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("multiple sketches"))
+        .stderr(contains("k=21, DNA; k=31, DNA"));
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args([
+            "-k",
+            "21",
+            "--containment",
+            "gene1.multi.sig",
+            "all.multi.sig",
+        ])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("100.0%"))
+        .stderr(contains("k=21, DNA"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn search_output_columns() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-o", "genes.sbt.json", "gene1.fa.sig", "gene2.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--containment", "-o", "matches.csv"])
+        .args(["all.sig", "genes.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut rdr = csv::Reader::from_path(tmp_dir.path().join("matches.csv"))?;
+    assert_eq!(
+        rdr.headers()?,
+        vec![
+            "similarity",
+            "name",
+            "filename",
+            "md5",
+            "ani",
+            "query_name",
+            "query_md5",
+            "ksize",
+            "moltype",
+            "scaled",
+            "num",
+            "intersect_bp",
+            "containment",
+            "max_containment",
+            "match_file",
+            "match_position",
+        ]
+    );
+
+    let rows = rdr
+        .deserialize::<HashMap<String, String>>()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["name"], "gene1.fa");
+    assert_eq!(rows[0]["query_name"], "ecoli.fna");
+    assert_eq!(rows[0]["ksize"], "31");
+    assert_eq!(rows[0]["moltype"], "dna");
+    assert_eq!(rows[0]["scaled"], "1");
+    assert_eq!(rows[0]["num"], "0");
+    assert_eq!(rows[0]["intersect_bp"], "2433");
+    assert!(rows[0]["containment"].starts_with("0.729"));
+    assert_eq!(rows[0]["max_containment"], "1.0");
+    assert!(rows[0]["match_file"].starts_with(".sbt.genes/"));
+    assert!(!rows[0]["match_position"].is_empty());
+    assert_ne!(rows[0]["match_position"], rows[1]["match_position"]);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn gather() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-o", "genes.sbt.json"])
+        .args(["gene1.fa.sig", "gene2.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("gather")
+        .args(["all.sig", "genes.sbt.json"])
+        .args(["--threshold-bp", "100"])
+        .args(["-o", "gather.csv"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("2.4 kbp       72.9%  100.0%    gene1.fa"))
+        .stdout(contains("0.9 kbp       27.1%  100.0%    gene2.fa"))
+        .stdout(contains("found 2 matches total;"))
+        .stdout(contains("the recovered matches hit 100.0% of the query"));
+
+    let mut rdr = csv::Reader::from_path(tmp_dir.path().join("gather.csv"))?;
+    let rows = rdr
+        .deserialize()
+        .collect::<Result<Vec<HashMap<String, String>>, _>>()?;
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["intersect_bp"], "2433");
+    assert_eq!(rows[0]["name"], "gene1.fa");
+    assert_eq!(rows[0]["average_abund"], "");
+    assert_eq!(rows[1]["remaining_bp"], "0");
+
+    // the default threshold is larger than the whole query
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("gather")
+        .args(["all.sig", "genes.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("found 0 matches total;"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn gather_abundance() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    // gene1 shows up twice in the query
+    let gene1 = fs::read_to_string(tmp_dir.path().join("gene1.fa"))?;
+    let genes = fs::read_to_string(tmp_dir.path().join("ecoli.fna"))?;
+    fs::write(tmp_dir.path().join("weighted.fa"), gene1 + &genes)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "31", "--scaled", "1", "-o", "weighted.sig"])
+        .arg("--track-abundance")
+        .arg("weighted.fa")
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-o", "genes.sbt.json"])
+        .args(["gene1.fa.sig", "gene2.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("gather")
+        .args(["weighted.sig", "genes.sbt.json"])
+        .args(["--threshold-bp", "100"])
+        .args(["-o", "gather.csv"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("overlap     p_query p_match avg_abund"))
+        .stdout(contains(
+            "2.4 kbp       72.9%  100.0%       2.0    gene1.fa",
+        ))
+        .stdout(contains(
+            "0.9 kbp       27.1%  100.0%       1.0    gene2.fa",
+        ));
+
+    let mut rdr = csv::Reader::from_path(tmp_dir.path().join("gather.csv"))?;
+    let rows = rdr
+        .deserialize()
+        .collect::<Result<Vec<HashMap<String, String>>, _>>()?;
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["name"], "gene1.fa");
+    assert!(rows[0]["f_unique_weighted"].starts_with("0.843"));
+    assert_eq!(rows[0]["average_abund"], "2.0");
+    assert_eq!(rows[0]["median_abund"], "2.0");
+    assert_eq!(rows[0]["std_abund"], "0.0");
+    assert!(rows[1]["f_unique_weighted"].starts_with("0.156"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn prefetch() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-o", "genes.sbt.json"])
+        .args(["gene1.fa.sig", "gene2.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("prefetch")
+        .args(["all.sig", "genes.sbt.json"])
+        .args(["--threshold-bp", "1000"])
+        .args(["-o", "prefetch.csv"])
+        .args(["--save-matches", "matches.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("total of 1 matching signatures."));
+
+    let mut rdr = csv::Reader::from_path(tmp_dir.path().join("prefetch.csv"))?;
+    let rows = rdr
+        .deserialize()
+        .collect::<Result<Vec<HashMap<String, String>>, _>>()?;
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["match_name"], "gene1.fa");
+    assert_eq!(rows[0]["intersect_bp"], "2433");
+    assert_eq!(rows[0]["f_match_query"], "1.0");
+    assert_eq!(rows[0]["query_bp"], "3336");
+
+    let sigs = Signature::from_path(tmp_dir.path().join("matches.sig"))?;
+    assert_eq!(sigs.len(), 1);
+    assert_eq!(sigs[0].name(), "gene1.fa");
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("prefetch")
+        .args(["all.sig", "genes.sbt.json"])
+        .args(["--threshold-bp", "0"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("total of 2 matching signatures."));
+
+    // the query is selected by molecule type, like in search
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("prefetch")
+        .args(["--protein", "all.sig", "genes.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("no query signature found"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn prefetch_mixed_scaled() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    for (input, output) in &[
+        ("ecoli.fna", "all10.sig"),
+        ("gene1.fa", "gene1.10.sig"),
+        ("gene2.fa", "gene2.10.sig"),
+    ] {
+        let mut cmd = Command::cargo_bin("decoct")?;
+        cmd.arg("compute")
+            .args(["-k", "31", "--scaled", "10", "-o", output, input])
+            .current_dir(&tmp_dir)
+            .assert()
+            .success();
+    }
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-o", "genes.sbt.json", "gene1.fa.sig", "gene2.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-o", "genes10.sbt.json", "gene1.10.sig", "gene2.10.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    // query at scaled=10, database at scaled=1
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("prefetch")
+        .args(["all10.sig", "genes.sbt.json"])
+        .args(["--threshold-bp", "1000"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("total of 1 matching signatures."));
+
+    // query at scaled=1, databases at scaled=10
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("prefetch")
+        .args(["all.sig", "genes10.sbt.json", "gene2.10.sig"])
+        .args(["--threshold-bp", "500"])
+        .args(["-o", "prefetch.csv"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("total of 3 matching signatures."));
+
+    let mut rdr = csv::Reader::from_path(tmp_dir.path().join("prefetch.csv"))?;
+    let rows = rdr
+        .deserialize()
+        .collect::<Result<Vec<HashMap<String, String>>, _>>()?;
+    assert_eq!(rows.len(), 3);
+    assert!(rows.iter().all(|row| row["match_filename"] != "all.sig"));
+    assert_eq!(
+        rows.iter()
+            .filter(|row| row["match_name"] == "gene2.fa")
+            .count(),
+        2
+    );
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("gather")
+        .args(["all10.sig", "genes.sbt.json"])
+        .args(["--threshold-bp", "100"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("found 2 matches total;"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn multigather() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-o", "genes.sbt.json"])
+        .args(["gene1.fa.sig", "gene2.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    fs::write(
+        tmp_dir.path().join("queries.txt"),
+        "all.sig\ngene2.fa.sig\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("multigather")
+        .args(["--query", "gene1.fa.sig"])
+        .args(["--query-from-file", "queries.txt"])
+        .args(["--db", "genes.sbt.json"])
+        .args(["--threshold-bp", "100"])
+        .args(["--output-dir", "results"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("found 1 matches for gene1.fa"))
+        .stdout(contains("found 2 matches for ecoli.fna"))
+        .stdout(contains("found 1 matches for gene2.fa"));
+
+    let results = tmp_dir.path().join("results");
+    for (name, n_matches) in &[("gene1.fa", 1), ("ecoli.fna", 2), ("gene2.fa", 1)] {
+        let mut rdr = csv::Reader::from_path(results.join(format!("{}.csv", name)))?;
+        assert_eq!(rdr.records().count(), *n_matches);
+    }
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("multisearch")
+        .args(["--query", "gene1.fa.sig", "all.sig"])
+        .args(["--db", "genes.sbt.json"])
+        .args(["--output-dir", "search"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("found 1 matches for gene1.fa"))
+        .stdout(contains("found 2 matches for ecoli.fna"));
+
+    assert!(tmp_dir.path().join("search").join("ecoli.fna.csv").exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn multisearch() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-o", "genes.sbt.json"])
+        .args(["gene1.fa.sig", "gene2.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args([
+            "-k",
+            "21,31",
+            "--scaled",
+            "1",
+            "-o",
+            "multik.sig",
+            "gene1.fa",
+        ])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    // only the k=31 sketch matches the SBT
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("multisearch")
+        .args(["--query", "multik.sig", "gene2.fa.sig"])
+        .args(["--db", "genes.sbt.json"])
+        .args(["--output-dir", "search"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("found 1 matches for gene1.fa"))
+        .stdout(contains("found 1 matches for gene2.fa"));
+
+    let outputs = fs::read_dir(tmp_dir.path().join("search"))?.count();
+    assert_eq!(outputs, 2);
+    let mut rdr = csv::Reader::from_path(tmp_dir.path().join("search").join("gene1.fa.csv"))?;
+    let rows = rdr
+        .deserialize()
+        .collect::<Result<Vec<HashMap<String, String>>, _>>()?;
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["ksize"], "31");
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "31", "--scaled", "0", "--num-hashes", "500"])
+        .args(["-o", "gene1.num.sig", "gene1.fa"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    // every query is checked before any results are written
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("multisearch")
+        .args(["--query", "gene2.fa.sig", "gene1.num.sig"])
+        .args(["--db", "genes.sbt.json"])
+        .args(["--output-dir", "incompatible"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("can't be searched against genes.sbt.json"));
+
+    assert!(!tmp_dir
+        .path()
+        .join("incompatible")
+        .join("gene2.fa.csv")
+        .exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn index_multiple_signatures() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    // both genes share the same filename
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args([
+            "-k",
+            "21,31",
+            "--scaled",
+            "1",
+            "--singleton",
+            "-o",
+            "genes.sig",
+        ])
+        .arg("ecoli.fna")
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-k", "31", "--dna", "-o", "genes.sbt.json", "genes.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--containment", "-o", "matches.csv"])
+        .args(["all.sig", "genes.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("2 matches:"));
+
+    let rows = csv::Reader::from_path(tmp_dir.path().join("matches.csv"))?
+        .deserialize::<HashMap<String, String>>()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(rows.len(), 2);
+    assert_ne!(rows[0]["name"], rows[1]["name"]);
+    assert_ne!(rows[0]["match_file"], rows[1]["match_file"]);
+    assert!(rows.iter().all(|row| row["ksize"] == "31"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn index_select_ksize() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "21,31", "--scaled", "1", "-o", "gene1.multi.sig"])
+        .arg("gene1.fa")
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    // gene2.fa.sig only has k=31
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-k", "21", "-o", "k21.sbt.json"])
+        .args(["gene1.multi.sig", "gene2.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stderr(contains("skipping gene2.fa from gene2.fa.sig"));
+
+    let description: serde_json::Value =
+        serde_json::from_reader(fs::File::open(tmp_dir.path().join("k21.sbt.json"))?)?;
+    assert_eq!(description["metadata"]["ksize"], 21);
+    assert_eq!(description["metadata"]["moltype"], "dna");
+    assert_eq!(description["leaves"].as_object().unwrap().len(), 1);
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["-k", "31", "gene1.fa.sig", "k21.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("ksize mismatch"));
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["--protein", "-o", "protein.sbt.json", "gene1.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("no signatures to index matching protein"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn index_from_file_and_directory() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    fs::create_dir_all(tmp_dir.path().join("sigs").join("sub"))?;
+    fs::copy(
+        tmp_dir.path().join("gene1.fa.sig"),
+        tmp_dir.path().join("sigs").join("gene1.fa.sig"),
+    )?;
+    fs::copy(
+        tmp_dir.path().join("gene1.fa.sig"),
+        tmp_dir.path().join("sigs").join("sub").join("copy.sig"),
+    )?;
+    fs::copy(
+        tmp_dir.path().join("gene2.fa.sig"),
+        tmp_dir.path().join("sigs").join("sub").join("gene2.fa.sig"),
+    )?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["--traverse-directory", "-o", "dir.sbt.json", "sigs"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stderr(contains("duplicate md5"))
+        .stderr(contains(
+            "indexed 2 signatures from 3 files, skipped 1 duplicates",
+        ));
+
+    fs::write(
+        tmp_dir.path().join("list.txt"),
+        "gene1.fa.sig\n\ngene2.fa.sig\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["--from-file", "list.txt", "-o", "list.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stderr(contains("indexed 2 signatures from 2 files"));
+
+    let mut cmd = assert_cmd::Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["--from-file", "-", "-o", "stdin.sbt.json", "all.sig"])
+        .current_dir(&tmp_dir)
+        .write_stdin("gene1.fa.sig\ngene2.fa.sig\n")
+        .assert()
+        .success()
+        .stderr(contains("indexed 3 signatures from 3 files"));
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--containment", "all.sig", "stdin.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("3 matches:"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn index_processes() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    // five fragments of the first gene, to get an unbalanced tree
+    let gene1 = fs::read_to_string(tmp_dir.path().join("gene1.fa"))?;
+    let seq: String = gene1.lines().skip(1).collect();
+    let fragments: String = (0..5)
+        .map(|i| format!(">fragment{}\n{}\n", i, &seq[i * 450..(i + 1) * 450]))
+        .collect();
+    fs::write(tmp_dir.path().join("fragments.fa"), fragments)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "31", "--scaled", "1", "--singleton"])
+        .args(["-o", "fragments.sig", "fragments.fa"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["-p", "2", "-o", "fragments.sbt.json", "fragments.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stderr(contains("indexed 5 signatures"));
+
+    let description: serde_json::Value =
+        serde_json::from_reader(fs::File::open(tmp_dir.path().join("fragments.sbt.json"))?)?;
+    assert_eq!(description["leaves"].as_object().unwrap().len(), 5);
+    assert_eq!(description["nodes"].as_object().unwrap().len(), 6);
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--containment", "-n", "0", "all.sig", "fragments.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("5 matches:"));
+
+    // nothing in common with the fragments, pruned at the root
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--containment", "gene2.fa.sig", "fragments.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("0 matches:"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn index_append() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+    fs::create_dir(tmp_dir.path().join("db"))?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["--append", "-o", "db/genes.sbt.json", "gene1.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stderr(contains("indexed 1 signatures"));
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["--append", "-o", "db/genes.sbt.json"])
+        .args(["gene1.fa.sig", "gene2.fa.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stderr(contains("skipping gene1.fa from gene1.fa.sig, already in"))
+        .stderr(contains("appended 1 signatures"));
+
+    assert!(!tmp_dir
+        .path()
+        .join("db")
+        .join("genes.sbt.json.tmp")
+        .exists());
+    let description: serde_json::Value = serde_json::from_reader(fs::File::open(
+        tmp_dir.path().join("db").join("genes.sbt.json"),
+    )?)?;
+    assert_eq!(description["leaves"].as_object().unwrap().len(), 2);
+    assert_eq!(description["storage"]["args"]["path"], ".sbt.genes");
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--containment", "all.sig", "db/genes.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("2 matches:"))
+        .stdout(contains("gene1.fa"))
+        .stdout(contains("gene2.fa"));
+
+    // appending doesn't change any file used by the current description
+    let storage = tmp_dir.path().join("db").join(".sbt.genes");
+    let stored: Vec<(PathBuf, Vec<u8>)> = fs::read_dir(&storage)?
+        .map(|entry| {
+            let path = entry?.path();
+            let data = fs::read(&path)?;
+            Ok((path, data))
+        })
+        .collect::<Result<_, std::io::Error>>()?;
+    fs::copy(
+        tmp_dir.path().join("db").join("genes.sbt.json"),
+        tmp_dir.path().join("db").join("old.sbt.json"),
+    )?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["--append", "-o", "db/genes.sbt.json", "all.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stderr(contains("appended 1 signatures"));
+
+    for (path, data) in &stored {
+        assert_eq!(&fs::read(path)?, data);
+    }
+    let description: serde_json::Value = serde_json::from_reader(fs::File::open(
+        tmp_dir.path().join("db").join("genes.sbt.json"),
+    )?)?;
+    assert_eq!(description["leaves"].as_object().unwrap().len(), 3);
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--containment", "all.sig", "db/old.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("2 matches:"));
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("search")
+        .args(["--containment", "all.sig", "db/genes.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains("3 matches:"))
+        .stdout(contains("gene1.fa"))
+        .stdout(contains("gene2.fa"));
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("index")
+        .args(["--append", "-k", "21", "-o", "db/genes.sbt.json", "all.sig"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("can't append k=21, DNA sketches"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn index_append_balanced() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_dir = TempDir::new()?;
+    prepare_scaled_sigs(&tmp_dir)?;
+
+    // many small signatures, from consecutive pieces of the genes
+    let genes = fs::read_to_string("tests/data/ecoli.genes.fna")?;
+    let sequence: String = genes
+        .lines()
+        .filter(|line| !line.starts_with('>'))
+        .collect();
+    let pieces: String = sequence
+        .as_bytes()
+        .chunks(45)
+        .filter(|chunk| chunk.len() == 45)
+        .enumerate()
+        .map(|(i, chunk)| format!(">piece{}\n{}\n", i, String::from_utf8_lossy(chunk)))
+        .collect();
+    fs::write(tmp_dir.path().join("pieces.fa"), pieces)?;
+
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("compute")
+        .args(["-k", "31", "--scaled", "1", "--singleton"])
+        .args(["-o", "pieces.sig", "pieces.fa"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success();
+
+    let sigs = Signature::from_path(tmp_dir.path().join("pieces.sig"))?;
+    assert!(sigs.len() > 64);
+    for (i, sig) in sigs.iter().enumerate() {
+        let filename = format!("piece{}.sig", i);
+        serde_json::to_writer(fs::File::create(tmp_dir.path().join(&filename))?, &[sig])?;
+
+        let mut cmd = Command::cargo_bin("decoct")?;
+        cmd.arg("index")
+            .args(["--append", "-o", "pieces.sbt.json", &filename])
+            .current_dir(&tmp_dir)
+            .assert()
+            .success();
+    }
+
+    // leaves fill the tree level by level, so it only gets as deep as needed
+    let description: serde_json::Value =
+        serde_json::from_reader(fs::File::open(tmp_dir.path().join("pieces.sbt.json"))?)?;
+    let leaves = description["leaves"].as_object().unwrap();
+    assert_eq!(leaves.len(), sigs.len());
+    let last: usize = leaves.keys().map(|pos| pos.parse().unwrap()).max().unwrap();
+    assert!(last < 2 * sigs.len());
+
+    // internal nodes hold the hashes of every leaf below them
+    let mut cmd = Command::cargo_bin("decoct")?;
+    cmd.arg("prefetch")
+        .args(["--threshold-bp", "0", "all.sig", "pieces.sbt.json"])
+        .current_dir(&tmp_dir)
+        .assert()
+        .success()
+        .stdout(contains(format!(
+            "total of {} matching signatures.",
+            sigs.len()
+        )));
+
+    Ok(())
+}